@@ -0,0 +1,191 @@
+// Copyright (c) 2021 Okko Hakola
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use std::{io::Cursor, time::Duration};
+
+use egui::{load::SizedTexture, Rect, TextureId, Vec2};
+use image::{
+    codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder},
+    error::{LimitError, LimitErrorKind},
+    AnimationDecoder, ImageError, ImageFormat, ImageResult, RgbaImage,
+};
+
+/// Browsers show frames without a delay (or a tiny one) for 100ms, do the same
+const DEFAULT_FRAME_DELAY: Duration = Duration::from_millis(100);
+
+/// An animated image uploaded once into an atlas texture, see `Gui::register_user_animation`.
+/// Frames are selected by their UV rect, so playing the animation never re-uploads pixels.
+/// Unregister it with `Gui::unregister_user_image(animation.texture_id())`.
+#[derive(Clone, Debug)]
+pub struct AnimatedTexture {
+    id: TextureId,
+    frame_size: [u32; 2],
+    frames: Vec<AnimationFrame>,
+    duration: Duration,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct AnimationFrame {
+    uv: Rect,
+    // Time from the start of the animation at which this frame ends
+    end: Duration,
+}
+
+impl AnimatedTexture {
+    /// The atlas containing all frames
+    pub fn texture_id(&self) -> TextureId {
+        self.id
+    }
+
+    /// Size of a single frame in pixels
+    pub fn size(&self) -> Vec2 {
+        Vec2::new(self.frame_size[0] as f32, self.frame_size[1] as f32)
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Length of one loop of the animation
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// Frame shown after `elapsed` time, looping the animation
+    pub fn frame_index(&self, elapsed: Duration) -> usize {
+        if self.duration.is_zero() {
+            return 0;
+        }
+        let time = Duration::from_nanos((elapsed.as_nanos() % self.duration.as_nanos()) as u64);
+        self.frames.partition_point(|frame| frame.end <= time).min(self.frames.len() - 1)
+    }
+
+    /// UV rect of a frame within the atlas, half a texel inside its edges
+    pub fn frame_uv(&self, index: usize) -> Rect {
+        self.frames[index].uv
+    }
+
+    /// UV rect of the frame shown after `elapsed` time
+    pub fn uv(&self, elapsed: Duration) -> Rect {
+        self.frame_uv(self.frame_index(elapsed))
+    }
+
+    /// Time until the frame shown after `elapsed` time changes, e.g. for
+    /// `egui::Context::request_repaint_after`. None for single frame images.
+    pub fn time_to_next_frame(&self, elapsed: Duration) -> Option<Duration> {
+        if self.frames.len() < 2 {
+            return None;
+        }
+        let time = Duration::from_nanos((elapsed.as_nanos() % self.duration.as_nanos()) as u64);
+        Some(self.frames[self.frame_index(elapsed)].end - time)
+    }
+
+    /// Image of the frame shown after `elapsed` time, at the size of a frame
+    pub fn image(&self, elapsed: Duration) -> egui::Image<'static> {
+        egui::Image::new(SizedTexture::new(self.id, self.size())).uv(self.uv(elapsed))
+    }
+}
+
+/// Frames of an animation packed into a grid, before upload
+pub(crate) struct AnimationAtlas {
+    pub pixels: RgbaImage,
+    frame_size: [u32; 2],
+    frames: Vec<AnimationFrame>,
+    duration: Duration,
+}
+
+impl AnimationAtlas {
+    /// Decodes all frames of a GIF, APNG or animated WebP. Other images become a single frame.
+    /// Fails with a limit error if the frames don't fit into an atlas of `max_side` pixels.
+    pub fn decode(file_bytes: &[u8], max_side: u32) -> ImageResult<Self> {
+        let frames = match image::guess_format(file_bytes)? {
+            ImageFormat::Gif => {
+                GifDecoder::new(Cursor::new(file_bytes))?.into_frames().collect_frames()?
+            }
+            ImageFormat::Png => {
+                let decoder = PngDecoder::new(Cursor::new(file_bytes))?;
+                if decoder.is_apng() {
+                    decoder.apng().into_frames().collect_frames()?
+                } else {
+                    vec![]
+                }
+            }
+            ImageFormat::WebP => {
+                let decoder = WebPDecoder::new(Cursor::new(file_bytes))?;
+                if decoder.has_animation() {
+                    decoder.into_frames().collect_frames()?
+                } else {
+                    vec![]
+                }
+            }
+            _ => vec![],
+        };
+        if frames.is_empty() {
+            let image = image::load_from_memory(file_bytes)?.to_rgba8();
+            return Self::pack(vec![(image, Duration::ZERO)], max_side);
+        }
+        let frames = frames
+            .into_iter()
+            .map(|frame| {
+                let (numer, denom) = frame.delay().numer_denom_ms();
+                let delay = Duration::from_micros(numer as u64 * 1000 / denom.max(1) as u64);
+                let delay =
+                    if delay <= Duration::from_millis(10) { DEFAULT_FRAME_DELAY } else { delay };
+                (frame.into_buffer(), delay)
+            })
+            .collect();
+        Self::pack(frames, max_side)
+    }
+
+    /// Packs equally sized frames into a roughly square grid, with fewer columns and more rows if
+    /// it would be wider than `max_side`
+    fn pack(frames: Vec<(RgbaImage, Duration)>, max_side: u32) -> ImageResult<Self> {
+        let (width, height) = frames[0].0.dimensions();
+        let too_large =
+            || ImageError::Limits(LimitError::from_kind(LimitErrorKind::DimensionError));
+        if width == 0 || height == 0 || width > max_side || height > max_side {
+            return Err(too_large());
+        }
+        let square_columns = (frames.len() as f32).sqrt().ceil() as u32;
+        let columns = square_columns.min(max_side / width);
+        let rows = (frames.len() as u32).div_ceil(columns);
+        if rows > max_side / height {
+            return Err(too_large());
+        }
+        let mut pixels = RgbaImage::new(width * columns, height * rows);
+        let atlas_size = [pixels.width() as f32, pixels.height() as f32];
+        let uv = |x: f32, y: f32| egui::pos2(x / atlas_size[0], y / atlas_size[1]);
+
+        let mut duration = Duration::ZERO;
+        let mut packed = vec![];
+        for (index, (frame, delay)) in frames.into_iter().enumerate() {
+            let x = (index as u32 % columns) * width;
+            let y = (index as u32 / columns) * height;
+            duration += delay;
+            // Half a texel in, so that linear filtering doesn't blend in neighboring frames
+            let (x0, y0) = (x as f32 + 0.5, y as f32 + 0.5);
+            let (x1, y1) = ((x + width) as f32 - 0.5, (y + height) as f32 - 0.5);
+            packed.push(AnimationFrame {
+                uv: Rect::from_min_max(uv(x0, y0), uv(x1, y1)),
+                end: duration,
+            });
+            image::imageops::replace(&mut pixels, &frame, x as i64, y as i64);
+        }
+        Ok(Self { pixels, frame_size: [width, height], frames: packed, duration })
+    }
+
+    pub fn into_texture(self, id: TextureId) -> AnimatedTexture {
+        AnimatedTexture {
+            id,
+            frame_size: self.frame_size,
+            frames: self.frames,
+            duration: self.duration,
+        }
+    }
+}
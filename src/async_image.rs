@@ -0,0 +1,173 @@
+// Copyright (c) 2021 Okko Hakola
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use std::{
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    sync::{mpsc, Arc, Mutex},
+};
+
+use ahash::AHashMap;
+use egui::TextureId;
+use vulkano::{format::Format, image::sampler::SamplerCreateInfo};
+
+use crate::{registrar::TextureRegistrar, utils::ImageCreationError};
+
+/// Where `Gui::load_user_image_async` reads an encoded image from
+#[derive(Clone, Debug)]
+pub enum ImageSource {
+    Path(PathBuf),
+    Bytes(Vec<u8>),
+}
+
+impl From<PathBuf> for ImageSource {
+    fn from(path: PathBuf) -> Self {
+        Self::Path(path)
+    }
+}
+
+impl From<&std::path::Path> for ImageSource {
+    fn from(path: &std::path::Path) -> Self {
+        Self::Path(path.to_owned())
+    }
+}
+
+impl From<Vec<u8>> for ImageSource {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::Bytes(bytes)
+    }
+}
+
+impl From<&[u8]> for ImageSource {
+    fn from(bytes: &[u8]) -> Self {
+        Self::Bytes(bytes.to_vec())
+    }
+}
+
+/// State of an image loaded with `Gui::load_user_image_async`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ImageLoadState {
+    /// Still being read, decoded or uploaded, the placeholder (if any) is shown
+    Loading,
+    /// Uploaded, the image has replaced the placeholder
+    Ready,
+    /// Reading, decoding or uploading failed, the placeholder stays
+    Failed(String),
+}
+
+/// Upper bound of the decoding threads, so that loading many images at once doesn't spawn a
+/// thread per image
+const MAX_DECODE_THREADS: usize = 4;
+
+type DecodeJob = Box<dyn FnOnce() + Send>;
+
+/// Worker threads shared by all image decoding, started with the first job. Jobs beyond the
+/// number of threads wait in a queue. The threads exit once the pool is dropped.
+#[derive(Default)]
+pub(crate) struct DecodePool {
+    sender: Mutex<Option<mpsc::Sender<DecodeJob>>>,
+}
+
+impl DecodePool {
+    pub fn execute(&self, job: impl FnOnce() + Send + 'static) {
+        let mut sender = self.sender.lock().unwrap();
+        sender.get_or_insert_with(Self::start).send(Box::new(job)).unwrap();
+    }
+
+    fn start() -> mpsc::Sender<DecodeJob> {
+        let (sender, receiver) = mpsc::channel::<DecodeJob>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let threads = std::thread::available_parallelism()
+            .map_or(1, |threads| threads.get())
+            .min(MAX_DECODE_THREADS);
+        for index in 0..threads {
+            let receiver = receiver.clone();
+            std::thread::Builder::new()
+                .name(format!("egui image decoder {}", index))
+                .spawn(move || loop {
+                    // The receiver is unlocked again before the job runs
+                    let Ok(job) = receiver.lock().unwrap().recv() else {
+                        return;
+                    };
+                    // A panicking decoder must not take the thread down with it, the queue
+                    // would stall once all threads are gone
+                    let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                })
+                .expect("Failed to spawn image decoding thread");
+        }
+        sender
+    }
+}
+
+/// Load states of all images loaded asynchronously that haven't been unregistered, shared with
+/// the worker threads
+#[derive(Clone, Default)]
+pub(crate) struct AsyncImageLoads {
+    states: Arc<Mutex<AHashMap<TextureId, ImageLoadState>>>,
+    pool: Arc<DecodePool>,
+}
+
+impl AsyncImageLoads {
+    pub fn state(&self, id: TextureId) -> Option<ImageLoadState> {
+        self.states.lock().unwrap().get(&id).cloned()
+    }
+
+    /// Forgets an image, true if it was loaded asynchronously
+    pub fn remove(&self, id: TextureId) -> bool {
+        self.states.lock().unwrap().remove(&id).is_some()
+    }
+
+    /// Decoding threads, shared with the texture loader
+    pub fn pool(&self) -> Arc<DecodePool> {
+        self.pool.clone()
+    }
+
+    /// Decodes the image on the pool's threads and queues it on `registrar` under `id`
+    pub fn spawn(
+        &self,
+        ctx: egui::Context,
+        registrar: TextureRegistrar,
+        id: TextureId,
+        source: ImageSource,
+        format: Format,
+        sampler_create_info: SamplerCreateInfo,
+    ) {
+        self.states.lock().unwrap().insert(id, ImageLoadState::Loading);
+        let shared = self.states.clone();
+        self.pool.execute(move || {
+            let decoded = match source {
+                ImageSource::Path(path) => image::open(path),
+                ImageSource::Bytes(bytes) => image::load_from_memory(&bytes),
+            };
+            let mut states = shared.lock().unwrap();
+            // Unregistered while loading, the texture must not come back
+            let Some(state) = states.get_mut(&id) else {
+                return;
+            };
+            match decoded {
+                Ok(image) => {
+                    let shared = shared.clone();
+                    let on_uploaded = Box::new(move |result: Result<(), ImageCreationError>| {
+                        // Unless unregistered in the meantime
+                        if let Some(state) = shared.lock().unwrap().get_mut(&id) {
+                            *state = match result {
+                                Ok(()) => ImageLoadState::Ready,
+                                Err(err) => ImageLoadState::Failed(format!("{:?}", err)),
+                            };
+                        }
+                    });
+                    // Queued under the lock, so that unregistering queues after it
+                    registrar.queue_dynamic(id, image, format, sampler_create_info, on_uploaded);
+                }
+                Err(err) => *state = ImageLoadState::Failed(err.to_string()),
+            }
+            ctx.request_repaint();
+        });
+    }
+}
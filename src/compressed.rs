@@ -0,0 +1,397 @@
+// Copyright (c) 2021 Okko Hakola
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use std::{borrow::Cow, sync::Arc};
+
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage},
+    command_buffer::{
+        AutoCommandBufferBuilder, BufferImageCopy, CopyBufferToImageInfo, PrimaryAutoCommandBuffer,
+    },
+    device::{DeviceOwned, Queue},
+    format::{CompressionType, Format, NumericFormat},
+    image::{
+        view::{ImageView, ImageViewCreateInfo, ImageViewType},
+        Image, ImageAspects, ImageCreateInfo, ImageFormatInfo, ImageSubresourceLayers,
+        ImageSubresourceRange, ImageType, ImageUsage,
+    },
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter},
+    DeviceSize,
+};
+
+use crate::utils::{upload_now, Allocators, ImageCreationError};
+
+/// Mip levels and array layers of a texture read from a KTX2 or DDS container
+struct ContainerTexture<'a> {
+    format: Format,
+    extent: [u32; 2],
+    array_layers: u32,
+    // Data of each mip level, and within that of each array layer
+    levels: Vec<Vec<Cow<'a, [u8]>>>,
+}
+
+/// Uploads a KTX2 texture with all its mip levels and layers (cube map faces become layers).
+/// If the device can't sample its format, block compressed data is decompressed to RGBA8 on the
+/// CPU. Supercompressed (Basis Universal, Zstandard) and 3D textures aren't supported.
+/// Only the first layer is drawn by egui.
+///
+/// The mip levels are only sampled if the texture's sampler allows it: the default
+/// `SamplerCreateInfo` has a `lod` of `0.0..=0.0`, which always samples the first level. Set e.g.
+/// `lod: 0.0..=LOD_CLAMP_NONE` with a `mipmap_mode` to use them.
+#[cfg(feature = "ktx2")]
+pub fn immutable_texture_from_ktx2(
+    allocators: &Allocators,
+    queue: Arc<Queue>,
+    file_bytes: &[u8],
+) -> Result<Arc<ImageView>, ImageCreationError> {
+    upload_now(allocators, queue, |cbb| record_texture_from_ktx2(allocators, cbb, file_bytes))
+}
+
+/// Like `record_texture_from_bytes`, for KTX2 files
+#[cfg(feature = "ktx2")]
+pub(crate) fn record_texture_from_ktx2(
+    allocators: &Allocators,
+    cbb: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    file_bytes: &[u8],
+) -> Result<Arc<ImageView>, ImageCreationError> {
+    let reader = ktx2::Reader::new(file_bytes).map_err(ImageCreationError::Ktx2)?;
+    let header = reader.header();
+    if let Some(scheme) = header.supercompression_scheme {
+        return Err(ImageCreationError::UnsupportedFormat(format!(
+            "KTX2 supercompression {:?}",
+            scheme
+        )));
+    }
+    if header.pixel_depth > 1 {
+        return Err(ImageCreationError::UnsupportedFormat("3D KTX2 texture".to_owned()));
+    }
+    let format = header
+        .format
+        .and_then(|format| Format::try_from(ash::vk::Format::from_raw(format.0.get() as i32)).ok())
+        .ok_or_else(|| {
+            ImageCreationError::UnsupportedFormat(format!("KTX2 format {:?}", header.format))
+        })?;
+    let array_layers = header.layer_count.max(1) * header.face_count.max(1);
+    // 1D textures have no height, but every texture has a width
+    if header.pixel_width == 0 {
+        return Err(ImageCreationError::InvalidData("KTX2 texture has zero width".to_owned()));
+    }
+    let extent = [header.pixel_width, header.pixel_height.max(1)];
+
+    let mut levels = vec![];
+    for (level, data) in reader.levels().enumerate() {
+        // Layers (and faces) of a level follow each other
+        let layer_size = level_size(format, mip_extent(extent, level as u32));
+        if data.len() as DeviceSize != layer_size * array_layers as DeviceSize {
+            return Err(ImageCreationError::InvalidData(format!(
+                "KTX2 level {} has {} bytes",
+                level,
+                data.len()
+            )));
+        }
+        levels.push(data.chunks_exact(layer_size as usize).map(Cow::Borrowed).collect());
+    }
+    upload_container_texture(
+        allocators,
+        cbb,
+        ContainerTexture { format, extent, array_layers, levels },
+    )
+}
+
+/// Uploads a DDS texture with all its mip levels and array layers, supporting BC1-7 and 8-bit
+/// RGBA / BGRA data. If the device can't sample its format, block compressed data is
+/// decompressed to RGBA8 on the CPU. Only the first layer is drawn by egui. Like for KTX2, the
+/// mip levels are only sampled with a sampler whose `lod` range includes them.
+#[cfg(feature = "dds")]
+pub fn immutable_texture_from_dds(
+    allocators: &Allocators,
+    queue: Arc<Queue>,
+    file_bytes: &[u8],
+) -> Result<Arc<ImageView>, ImageCreationError> {
+    upload_now(allocators, queue, |cbb| record_texture_from_dds(allocators, cbb, file_bytes))
+}
+
+/// Like `record_texture_from_bytes`, for DDS files
+#[cfg(feature = "dds")]
+pub(crate) fn record_texture_from_dds(
+    allocators: &Allocators,
+    cbb: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    file_bytes: &[u8],
+) -> Result<Arc<ImageView>, ImageCreationError> {
+    let dds = ddsfile::Dds::read(file_bytes).map_err(ImageCreationError::Dds)?;
+    let format = dds_format(&dds).ok_or_else(|| {
+        ImageCreationError::UnsupportedFormat(format!(
+            "DDS format {:?} / {:?}",
+            dds.get_dxgi_format(),
+            dds.get_d3d_format()
+        ))
+    })?;
+    let extent = [dds.get_width(), dds.get_height()];
+    if extent.contains(&0) {
+        return Err(ImageCreationError::InvalidData(format!("DDS texture size {:?}", extent)));
+    }
+    let mip_levels = dds.get_num_mipmap_levels().max(1);
+    let array_layers = dds.get_num_array_layers().max(1);
+
+    // Unlike KTX2, each layer holds its whole mip chain
+    let mut levels = vec![vec![]; mip_levels as usize];
+    let mut offset = 0;
+    for _ in 0..array_layers {
+        for (level, layers) in levels.iter_mut().enumerate() {
+            let size = level_size(format, mip_extent(extent, level as u32)) as usize;
+            let data = dds.data.get(offset..offset + size).ok_or_else(|| {
+                ImageCreationError::InvalidData(format!("DDS data ends at {} bytes", offset))
+            })?;
+            layers.push(Cow::Borrowed(data));
+            offset += size;
+        }
+    }
+    upload_container_texture(
+        allocators,
+        cbb,
+        ContainerTexture { format, extent, array_layers, levels },
+    )
+}
+
+#[cfg(feature = "dds")]
+fn dds_format(dds: &ddsfile::Dds) -> Option<Format> {
+    use ddsfile::{D3DFormat, DxgiFormat};
+
+    if let Some(format) = dds.get_dxgi_format() {
+        return Some(match format {
+            DxgiFormat::BC1_UNorm => Format::BC1_RGBA_UNORM_BLOCK,
+            DxgiFormat::BC1_UNorm_sRGB => Format::BC1_RGBA_SRGB_BLOCK,
+            DxgiFormat::BC2_UNorm => Format::BC2_UNORM_BLOCK,
+            DxgiFormat::BC2_UNorm_sRGB => Format::BC2_SRGB_BLOCK,
+            DxgiFormat::BC3_UNorm => Format::BC3_UNORM_BLOCK,
+            DxgiFormat::BC3_UNorm_sRGB => Format::BC3_SRGB_BLOCK,
+            DxgiFormat::BC4_UNorm => Format::BC4_UNORM_BLOCK,
+            DxgiFormat::BC4_SNorm => Format::BC4_SNORM_BLOCK,
+            DxgiFormat::BC5_UNorm => Format::BC5_UNORM_BLOCK,
+            DxgiFormat::BC5_SNorm => Format::BC5_SNORM_BLOCK,
+            DxgiFormat::BC6H_UF16 => Format::BC6H_UFLOAT_BLOCK,
+            DxgiFormat::BC6H_SF16 => Format::BC6H_SFLOAT_BLOCK,
+            DxgiFormat::BC7_UNorm => Format::BC7_UNORM_BLOCK,
+            DxgiFormat::BC7_UNorm_sRGB => Format::BC7_SRGB_BLOCK,
+            DxgiFormat::R8G8B8A8_UNorm => Format::R8G8B8A8_UNORM,
+            DxgiFormat::R8G8B8A8_UNorm_sRGB => Format::R8G8B8A8_SRGB,
+            DxgiFormat::B8G8R8A8_UNorm => Format::B8G8R8A8_UNORM,
+            DxgiFormat::B8G8R8A8_UNorm_sRGB => Format::B8G8R8A8_SRGB,
+            _ => return None,
+        });
+    }
+    match dds.get_d3d_format()? {
+        D3DFormat::DXT1 => Some(Format::BC1_RGBA_UNORM_BLOCK),
+        D3DFormat::DXT2 | D3DFormat::DXT3 => Some(Format::BC2_UNORM_BLOCK),
+        D3DFormat::DXT4 | D3DFormat::DXT5 => Some(Format::BC3_UNORM_BLOCK),
+        D3DFormat::A8R8G8B8 => Some(Format::B8G8R8A8_UNORM),
+        D3DFormat::A8B8G8R8 => Some(Format::R8G8B8A8_UNORM),
+        _ => None,
+    }
+}
+
+fn mip_extent(extent: [u32; 2], level: u32) -> [u32; 2] {
+    extent.map(|side| (side >> level).max(1))
+}
+
+/// Bytes of a tightly packed image of `format`, rounded up to whole blocks
+fn level_size(format: Format, extent: [u32; 2]) -> DeviceSize {
+    let [block_width, block_height, _] = format.block_extent();
+    extent[0].div_ceil(block_width) as DeviceSize
+        * extent[1].div_ceil(block_height) as DeviceSize
+        * format.block_size()
+}
+
+fn upload_container_texture(
+    allocators: &Allocators,
+    cbb: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    texture: ContainerTexture<'_>,
+) -> Result<Arc<ImageView>, ImageCreationError> {
+    let supported = cbb
+        .device()
+        .physical_device()
+        .image_format_properties(ImageFormatInfo {
+            format: texture.format,
+            usage: ImageUsage::TRANSFER_DST | ImageUsage::SAMPLED,
+            ..Default::default()
+        })
+        .is_ok_and(|properties| properties.is_some());
+    let texture = if supported { texture } else { decompress(texture)? };
+
+    // All levels and layers in one buffer, with a copy region for each
+    let mut regions = vec![];
+    let mut buffer_offset = 0;
+    for (mip_level, layers) in texture.levels.iter().enumerate() {
+        let [width, height] = mip_extent(texture.extent, mip_level as u32);
+        for (layer, data) in layers.iter().enumerate() {
+            regions.push(BufferImageCopy {
+                buffer_offset,
+                image_subresource: ImageSubresourceLayers {
+                    aspects: ImageAspects::COLOR,
+                    mip_level: mip_level as u32,
+                    array_layers: layer as u32..layer as u32 + 1,
+                },
+                image_extent: [width, height, 1],
+                ..Default::default()
+            });
+            buffer_offset += data.len() as DeviceSize;
+        }
+    }
+    let texture_data_buffer = Buffer::from_iter(
+        allocators.memory.clone(),
+        BufferCreateInfo { usage: BufferUsage::TRANSFER_SRC, ..Default::default() },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..Default::default()
+        },
+        texture.levels.iter().flatten().flat_map(|data| data.iter().copied()).collect::<Vec<_>>(),
+    )
+    .map_err(ImageCreationError::AllocateBuffer)?;
+
+    let mip_levels = texture.levels.len() as u32;
+    let image = Image::new(
+        allocators.memory.clone(),
+        ImageCreateInfo {
+            image_type: ImageType::Dim2d,
+            format: texture.format,
+            extent: [texture.extent[0], texture.extent[1], 1],
+            mip_levels,
+            array_layers: texture.array_layers,
+            usage: ImageUsage::TRANSFER_DST | ImageUsage::SAMPLED,
+            ..Default::default()
+        },
+        AllocationCreateInfo::default(),
+    )
+    .map_err(ImageCreationError::AllocateImage)?;
+
+    cbb.copy_buffer_to_image(CopyBufferToImageInfo {
+        regions: regions.into(),
+        ..CopyBufferToImageInfo::buffer_image(texture_data_buffer, image.clone())
+    })
+    .map_err(ImageCreationError::Validation)?;
+
+    // egui samples a plain 2D texture, so the view only covers the first layer
+    ImageView::new(
+        image.clone(),
+        ImageViewCreateInfo {
+            view_type: ImageViewType::Dim2d,
+            subresource_range: ImageSubresourceRange {
+                aspects: ImageAspects::COLOR,
+                mip_levels: 0..mip_levels,
+                array_layers: 0..1,
+            },
+            ..ImageViewCreateInfo::from_image(&image)
+        },
+    )
+    .map_err(ImageCreationError::Vulkan)
+}
+
+/// Decodes all levels and layers to RGBA8, for formats the device can't sample
+fn decompress(
+    texture: ContainerTexture<'_>,
+) -> Result<ContainerTexture<'static>, ImageCreationError> {
+    let unsupported = || {
+        ImageCreationError::UnsupportedFormat(format!(
+            "{:?}, neither supported by the device nor decodable",
+            texture.format
+        ))
+    };
+    // No CPU decoders for these, see `decompress_rgba8`
+    if matches!(
+        texture.format,
+        Format::BC2_UNORM_BLOCK
+            | Format::BC2_SRGB_BLOCK
+            | Format::BC6H_UFLOAT_BLOCK
+            | Format::BC6H_SFLOAT_BLOCK
+            | Format::BC4_SNORM_BLOCK
+            | Format::BC5_SNORM_BLOCK
+            | Format::EAC_R11_SNORM_BLOCK
+            | Format::EAC_R11G11_SNORM_BLOCK
+    ) {
+        return Err(unsupported());
+    }
+    let mut levels = vec![];
+    for (level, layers) in texture.levels.iter().enumerate() {
+        let extent = mip_extent(texture.extent, level as u32);
+        let layers = layers
+            .iter()
+            .map(|data| decompress_rgba8(texture.format, extent, data).map(Cow::Owned))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(unsupported)?;
+        levels.push(layers);
+    }
+    let format = if texture.format.numeric_format_color() == Some(NumericFormat::SRGB) {
+        Format::R8G8B8A8_SRGB
+    } else {
+        Format::R8G8B8A8_UNORM
+    };
+    Ok(ContainerTexture {
+        format,
+        extent: texture.extent,
+        array_layers: texture.array_layers,
+        levels,
+    })
+}
+
+/// None if `format` isn't block compressed or has no CPU decoder (BC2, BC6H and signed formats)
+fn decompress_rgba8(format: Format, extent: [u32; 2], data: &[u8]) -> Option<Vec<u8>> {
+    use texture2ddecoder::*;
+
+    let [width, height] = extent.map(|side| side as usize);
+    let mut pixels = vec![0u32; width * height];
+    let decoded = match format {
+        Format::BC1_RGB_UNORM_BLOCK
+        | Format::BC1_RGB_SRGB_BLOCK
+        | Format::BC1_RGBA_UNORM_BLOCK
+        | Format::BC1_RGBA_SRGB_BLOCK => decode_bc1(data, width, height, &mut pixels),
+        Format::BC3_UNORM_BLOCK | Format::BC3_SRGB_BLOCK => {
+            decode_bc3(data, width, height, &mut pixels)
+        }
+        Format::BC4_UNORM_BLOCK => decode_bc4(data, width, height, &mut pixels),
+        Format::BC5_UNORM_BLOCK => decode_bc5(data, width, height, &mut pixels),
+        Format::BC7_UNORM_BLOCK | Format::BC7_SRGB_BLOCK => {
+            decode_bc7(data, width, height, &mut pixels)
+        }
+        Format::ETC2_R8G8B8_UNORM_BLOCK | Format::ETC2_R8G8B8_SRGB_BLOCK => {
+            decode_etc2_rgb(data, width, height, &mut pixels)
+        }
+        Format::ETC2_R8G8B8A1_UNORM_BLOCK | Format::ETC2_R8G8B8A1_SRGB_BLOCK => {
+            decode_etc2_rgba1(data, width, height, &mut pixels)
+        }
+        Format::ETC2_R8G8B8A8_UNORM_BLOCK | Format::ETC2_R8G8B8A8_SRGB_BLOCK => {
+            decode_etc2_rgba8(data, width, height, &mut pixels)
+        }
+        Format::EAC_R11_UNORM_BLOCK => decode_eacr(data, width, height, &mut pixels),
+        Format::EAC_R11G11_UNORM_BLOCK => decode_eacrg(data, width, height, &mut pixels),
+        _ if format.compression() == Some(CompressionType::ASTC_LDR) => {
+            let [block_width, block_height, _] = format.block_extent();
+            decode_astc(
+                data,
+                width,
+                height,
+                block_width as usize,
+                block_height as usize,
+                &mut pixels,
+            )
+        }
+        _ => return None,
+    };
+    decoded.ok()?;
+    // Decoded pixels are packed as 0xAARRGGBB
+    Some(
+        pixels
+            .iter()
+            .flat_map(|pixel| {
+                let [b, g, r, a] = pixel.to_le_bytes();
+                [r, g, b, a]
+            })
+            .collect(),
+    )
+}
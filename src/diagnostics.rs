@@ -0,0 +1,85 @@
+// Copyright (c) 2021 Okko Hakola
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Minimum time between two messages of a rate limited call site
+const RATE_LIMIT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Logs a warning through `tracing` if that feature is enabled, through `log` otherwise
+macro_rules! log_warn {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        tracing::warn!($($arg)+);
+        #[cfg(not(feature = "tracing"))]
+        log::warn!($($arg)+);
+    }};
+}
+
+/// Logs an error through `tracing` if that feature is enabled, through `log` otherwise
+#[cfg(any(feature = "persistence", feature = "recording"))]
+macro_rules! log_error {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        tracing::error!($($arg)+);
+        #[cfg(not(feature = "tracing"))]
+        log::error!($($arg)+);
+    }};
+}
+
+/// Like `log_warn!`, but logs at most once every few seconds per call site, for problems that
+/// repeat every frame. The next message tells how many were suppressed in between.
+macro_rules! warn_rate_limited {
+    ($fmt:literal $(, $arg:expr)* $(,)?) => {{
+        static LIMITER: $crate::diagnostics::RateLimiter = $crate::diagnostics::RateLimiter::new();
+        match LIMITER.check() {
+            Some(0) => $crate::diagnostics::log_warn!($fmt $(, $arg)*),
+            Some(suppressed) => $crate::diagnostics::log_warn!(
+                concat!($fmt, " ({} similar messages suppressed)") $(, $arg)*, suppressed
+            ),
+            None => {}
+        }
+    }};
+}
+
+#[cfg(any(feature = "persistence", feature = "recording"))]
+pub(crate) use log_error;
+pub(crate) use log_warn;
+pub(crate) use warn_rate_limited;
+
+pub(crate) struct RateLimiter {
+    // Time of the last logged message, and the number of messages suppressed since
+    state: Mutex<Option<(Instant, usize)>>,
+}
+
+impl RateLimiter {
+    pub const fn new() -> Self {
+        Self { state: Mutex::new(None) }
+    }
+
+    /// Some(number of suppressed messages) if a message may be logged now
+    pub fn check(&self) -> Option<usize> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        match &mut *state {
+            Some((last, suppressed)) if now.duration_since(*last) < RATE_LIMIT_INTERVAL => {
+                *suppressed += 1;
+                None
+            }
+            _ => {
+                let suppressed = state.map_or(0, |(_, suppressed)| suppressed);
+                *state = Some((now, 0));
+                Some(suppressed)
+            }
+        }
+    }
+}
@@ -0,0 +1,235 @@
+// Copyright (c) 2021 Okko Hakola
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use std::{fmt, sync::Arc};
+
+use vulkano::{
+    format::{FormatFeatures, NumericFormat},
+    image::{
+        sampler::{Filter, SamplerCreateInfo, SamplerMipmapMode},
+        view::{ImageView, ImageViewCreateInfo, ImageViewType},
+        Image, ImageAspects, ImageSubresourceRange, ImageType, ImageUsage,
+    },
+};
+
+/// Which aspect of an image to inspect
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InspectAspect {
+    #[default]
+    Color,
+    Depth,
+    Stencil,
+}
+
+/// Which channels of an image to show
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InspectChannel {
+    /// All channels, including alpha
+    #[default]
+    Rgba,
+    /// Color channels, opaque
+    Rgb,
+    /// A single channel as grayscale
+    Red,
+    Green,
+    Blue,
+    Alpha,
+}
+
+/// How `Gui::register_user_image_inspector` shows an image
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InspectOptions {
+    pub aspect: InspectAspect,
+    pub mip_level: u32,
+    /// Array layer to show. Faces of cube maps are layers too, face `f` of cube `c` being layer
+    /// `6 * c + f`.
+    pub array_layer: u32,
+    /// Depth slice to show of 3D images
+    pub slice: u32,
+    pub channel: InspectChannel,
+    /// Values shown as black and white, e.g. `[0.0, 65535.0]` for an ID buffer or
+    /// `[0.99, 1.0]` to make out depth close to the far plane. Defaults to `[0.0, 1.0]`.
+    pub value_range: [f32; 2],
+}
+
+impl Default for InspectOptions {
+    fn default() -> Self {
+        InspectOptions {
+            aspect: InspectAspect::Color,
+            mip_level: 0,
+            array_layer: 0,
+            slice: 0,
+            channel: InspectChannel::Rgba,
+            value_range: [0.0, 1.0],
+        }
+    }
+}
+
+/// Why an image can't be inspected with the given options
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InspectError {
+    /// No inspected image is registered under this id
+    UnknownTexture(egui::TextureId),
+    /// The image wasn't created with `ImageUsage::SAMPLED`
+    NotSampled,
+    /// 1D images can't be shown by the inspector shader
+    Unsupported1d,
+    /// The image's format has no such aspect
+    MissingAspect(InspectAspect),
+    MipLevelOutOfRange,
+    ArrayLayerOutOfRange,
+    SliceOutOfRange,
+}
+
+impl fmt::Display for InspectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InspectError::UnknownTexture(id) => {
+                write!(f, "no inspected image is registered as {:?}", id)
+            }
+            InspectError::NotSampled => write!(f, "image wasn't created with SAMPLED usage"),
+            InspectError::Unsupported1d => write!(f, "1D images can't be inspected"),
+            InspectError::MissingAspect(aspect) => write!(f, "image has no {:?} aspect", aspect),
+            InspectError::MipLevelOutOfRange => write!(f, "mip level is out of range"),
+            InspectError::ArrayLayerOutOfRange => write!(f, "array layer is out of range"),
+            InspectError::SliceOutOfRange => write!(f, "slice is out of range"),
+        }
+    }
+}
+
+impl std::error::Error for InspectError {}
+
+/// What the shader samples, each needing its own shader variant
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum SampleType {
+    Float,
+    Uint,
+    Sint,
+}
+
+/// An image registered for inspection, with its view of the selected subresource
+pub(crate) struct InspectedImage {
+    pub image: Arc<Image>,
+    pub options: InspectOptions,
+    pub sampler_create_info: SamplerCreateInfo,
+    pub view: Arc<ImageView>,
+    pub sample_type: SampleType,
+}
+
+impl InspectedImage {
+    pub fn new(
+        image: Arc<Image>,
+        options: InspectOptions,
+        sampler_create_info: SamplerCreateInfo,
+    ) -> Result<Self, InspectError> {
+        if !image.usage().intersects(ImageUsage::SAMPLED) {
+            return Err(InspectError::NotSampled);
+        }
+        if image.image_type() == ImageType::Dim1d {
+            return Err(InspectError::Unsupported1d);
+        }
+        let (aspects, sample_type) = match options.aspect {
+            InspectAspect::Color => {
+                let sample_type = match image.format().numeric_format_color() {
+                    Some(NumericFormat::UINT) => SampleType::Uint,
+                    Some(NumericFormat::SINT) => SampleType::Sint,
+                    _ => SampleType::Float,
+                };
+                (ImageAspects::COLOR, sample_type)
+            }
+            InspectAspect::Depth => (ImageAspects::DEPTH, SampleType::Float),
+            InspectAspect::Stencil => (ImageAspects::STENCIL, SampleType::Uint),
+        };
+        if !image.format().aspects().contains(aspects) {
+            return Err(InspectError::MissingAspect(options.aspect));
+        }
+        if options.mip_level >= image.mip_levels() {
+            return Err(InspectError::MipLevelOutOfRange);
+        }
+        if options.array_layer >= image.array_layers() {
+            return Err(InspectError::ArrayLayerOutOfRange);
+        }
+        check_slice(&image, &options)?;
+        let is_3d = image.image_type() == ImageType::Dim3d;
+
+        let view = ImageView::new(
+            image.clone(),
+            ImageViewCreateInfo {
+                view_type: if is_3d { ImageViewType::Dim3d } else { ImageViewType::Dim2d },
+                subresource_range: ImageSubresourceRange {
+                    aspects,
+                    mip_levels: options.mip_level..options.mip_level + 1,
+                    array_layers: options.array_layer..options.array_layer + 1,
+                },
+                ..ImageViewCreateInfo::from_image(&image)
+            },
+        )
+        .unwrap();
+        Ok(InspectedImage { image, options, sampler_create_info, view, sample_type })
+    }
+
+    /// Switches to `options` if they select the same subresource, the others only change push
+    /// constants. False if a new view is needed.
+    pub fn update_in_place(&mut self, options: InspectOptions) -> Result<bool, InspectError> {
+        let subresource =
+            |options: &InspectOptions| (options.aspect, options.mip_level, options.array_layer);
+        if subresource(&options) != subresource(&self.options) {
+            return Ok(false);
+        }
+        check_slice(&self.image, &options)?;
+        self.options = options;
+        Ok(true)
+    }
+
+    pub fn is_3d(&self) -> bool {
+        self.view.view_type() == ImageViewType::Dim3d
+    }
+
+    /// The requested sampler, made nearest where the format can't be filtered linearly (e.g.
+    /// integer formats)
+    pub fn sampler_create_info(&self) -> SamplerCreateInfo {
+        if self.sample_type == SampleType::Float
+            && self.view.format_features().intersects(FormatFeatures::SAMPLED_IMAGE_FILTER_LINEAR)
+        {
+            return self.sampler_create_info.clone();
+        }
+        SamplerCreateInfo {
+            mag_filter: Filter::Nearest,
+            min_filter: Filter::Nearest,
+            mipmap_mode: SamplerMipmapMode::Nearest,
+            ..self.sampler_create_info.clone()
+        }
+    }
+
+    /// Channel selector of the inspector shader
+    pub fn channel_index(&self) -> i32 {
+        match self.options.channel {
+            InspectChannel::Red => 0,
+            InspectChannel::Green => 1,
+            InspectChannel::Blue => 2,
+            InspectChannel::Alpha => 3,
+            InspectChannel::Rgb => 4,
+            InspectChannel::Rgba => 5,
+        }
+    }
+
+    /// Normalized depth of the slice shown of a 3D image
+    pub fn slice_coordinate(&self) -> f32 {
+        let depth = (self.image.extent()[2] >> self.options.mip_level).max(1);
+        (self.options.slice as f32 + 0.5) / depth as f32
+    }
+}
+
+fn check_slice(image: &Image, options: &InspectOptions) -> Result<(), InspectError> {
+    let is_3d = image.image_type() == ImageType::Dim3d;
+    if is_3d && options.slice >= (image.extent()[2] >> options.mip_level).max(1) {
+        return Err(InspectError::SliceOutOfRange);
+    }
+    Ok(())
+}
@@ -6,26 +6,53 @@
 // at your option. All files in the project carrying such
 // notice may not be copied, modified, or distributed except
 // according to those terms.
-use std::sync::Arc;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use egui::{ClippedPrimitive, TexturesDelta};
-use egui_winit::EventResponse;
+#[cfg(feature = "accesskit")]
+use egui_winit::accesskit_winit;
+use egui_winit::{ActionRequested, EventResponse};
 use vulkano::{
     command_buffer::SecondaryAutoCommandBuffer,
     device::Queue,
     format::{Format, NumericFormat},
-    image::{sampler::SamplerCreateInfo, view::ImageView, SampleCount},
+    image::{sampler::SamplerCreateInfo, view::ImageView, Image, SampleCount},
     render_pass::Subpass,
     swapchain::Surface,
     sync::GpuFuture,
 };
-use winit::window::Window;
+use winit::{event_loop::EventLoopProxy, window::Window};
 
 #[cfg(feature = "image")]
-use crate::utils::immutable_texture_from_file;
+use crate::animation::{AnimatedTexture, AnimationAtlas};
+#[cfg(feature = "image")]
+use crate::async_image::{AsyncImageLoads, ImageLoadState, ImageSource};
+#[cfg(any(feature = "persistence", feature = "recording"))]
+use crate::diagnostics::log_error;
+#[cfg(feature = "recording")]
+use crate::recording::{InputRecorder, InputReplay};
+#[cfg(feature = "image")]
+use crate::texture_loader::{self, VulkanoTextureLoader};
+#[cfg(any(feature = "ktx2", feature = "dds"))]
+use crate::utils::ImageCreationError;
+#[cfg(feature = "svg")]
 use crate::{
-    renderer::{RenderResources, Renderer},
-    utils::immutable_texture_from_bytes,
+    diagnostics::log_warn,
+    svg::{SvgError, SvgSize, SvgTexture},
+};
+use crate::{
+    inspect::{InspectError, InspectOptions},
+    profiling::profile_scope,
+    registrar::TextureRegistrar,
+    renderer::{
+        DrawError, MissingTexturePolicy, RenderDebugMode, RenderResources, RenderStats, Renderer,
+        RendererSettings, TextureUpdateError, UnsupportedCallbackPolicy,
+    },
+    timestamps::GpuTimings,
+    video::{VideoError, VideoPlane, YuvColorSpace, YuvFormat, YuvRange},
 };
 
 pub struct GuiConfig {
@@ -42,6 +69,47 @@ pub struct GuiConfig {
     /// Multisample count. Defaults to 1. If you use more than 1, you'll have to ensure your
     /// pipeline and target image matches that.
     pub samples: SampleCount,
+    /// Keeps CPU copies of managed textures (e.g. the font atlas) and of user images registered from
+    /// bytes or files, so that they can be re-uploaded by `Gui::recreate_on_device` after the device
+    /// has been lost. Defaults to false.
+    pub retain_texture_data: bool,
+    /// Whether `Gui::update_device_event` reports raw mouse motion as consumed while egui wants
+    /// pointer input, e.g. so that a camera doesn't turn while dragging a slider. Defaults to true.
+    pub consume_raw_mouse_motion: bool,
+    /// Writes GPU timestamps around the GUI pass and each paint callback, read back through
+    /// `Gui::gpu_timings`. Ignored if the queue doesn't support timestamps. Defaults to false.
+    ///
+    /// With `Gui::new_with_subpass`, the command buffer returned by `draw_on_subpass_image` must
+    /// be submitted to the queue the `Gui` was created with, as its queries are reset in a
+    /// separate submission on that queue.
+    pub gpu_timestamps: bool,
+    /// Debug visualization of scissor rects, draw calls, triangles or overdraw, drawn by the
+    /// renderer. Can be switched later with `Gui::set_debug_mode`. Defaults to `Off`.
+    pub debug_mode: RenderDebugMode,
+    /// What to do with meshes whose texture doesn't exist. Defaults to skipping them with a
+    /// warning.
+    pub missing_texture_policy: MissingTexturePolicy,
+    /// What to do with paint callbacks that aren't a `CallbackFn`. Defaults to skipping them with
+    /// a warning.
+    pub unsupported_callback_policy: UnsupportedCallbackPolicy,
+    /// How many frames the application has in flight at most, i.e. submitted but not finished on
+    /// the GPU. Videos upload into a ring of `frames_in_flight + 1` images, so that a frame is
+    /// never written into an image a pending frame draws from. GPU timestamps still without
+    /// results after this many frames are given up on. Defaults to 2.
+    pub frames_in_flight: u32,
+    /// Color shown by images loaded with `Gui::load_user_image_async` until they are decoded. If
+    /// None, their texture doesn't exist until then and `missing_texture_policy` applies.
+    /// Defaults to None.
+    #[cfg(feature = "image")]
+    pub loading_placeholder: Option<egui::Color32>,
+    /// Where egui's memory (window positions, collapsed headers, panel sizes etc.) is persisted.
+    /// If set, it is loaded on creation and saved every `persistence_interval` as well as when the
+    /// window is closed. Defaults to None.
+    #[cfg(feature = "persistence")]
+    pub persistence_path: Option<std::path::PathBuf>,
+    /// How often egui's memory is saved to `persistence_path`. Defaults to 30 seconds.
+    #[cfg(feature = "persistence")]
+    pub persistence_interval: Duration,
 }
 
 impl Default for GuiConfig {
@@ -50,11 +118,35 @@ impl Default for GuiConfig {
             allow_srgb_render_target: false,
             is_overlay: false,
             samples: SampleCount::Sample1,
+            retain_texture_data: false,
+            consume_raw_mouse_motion: true,
+            gpu_timestamps: false,
+            debug_mode: RenderDebugMode::Off,
+            missing_texture_policy: MissingTexturePolicy::Skip,
+            unsupported_callback_policy: UnsupportedCallbackPolicy::Skip,
+            frames_in_flight: 2,
+            #[cfg(feature = "image")]
+            loading_placeholder: None,
+            #[cfg(feature = "persistence")]
+            persistence_path: None,
+            #[cfg(feature = "persistence")]
+            persistence_interval: Duration::from_secs(30),
         }
     }
 }
 
 impl GuiConfig {
+    fn renderer_settings(&self) -> RendererSettings {
+        RendererSettings {
+            retain_texture_data: self.retain_texture_data,
+            gpu_timestamps: self.gpu_timestamps,
+            debug_mode: self.debug_mode,
+            missing_texture_policy: self.missing_texture_policy,
+            unsupported_callback_policy: self.unsupported_callback_policy,
+            frames_in_flight: self.frames_in_flight,
+        }
+    }
+
     pub fn validate(&self, output_format: Format) {
         if output_format.numeric_format_color().unwrap() == NumericFormat::SRGB {
             assert!(
@@ -68,13 +160,57 @@ impl GuiConfig {
     }
 }
 
+/// Sent through the event loop proxy given to `Gui::set_repaint_proxy` when egui requests a
+/// repaint, possibly from a background thread.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RequestRepaintEvent {
+    /// The viewport that should be repainted.
+    pub viewport_id: egui::ViewportId,
+    /// When to repaint. Request a redraw once this has passed, e.g. by waiting with
+    /// `ControlFlow::WaitUntil(when)`.
+    pub when: Instant,
+}
+
+/// What the app has to act on after a frame, returned by `Gui::end_frame`
+#[must_use]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameOutput {
+    /// egui sent `ViewportCommand::Close` (and no `CancelClose`) during the frame. The app should
+    /// exit its event loop or close the window.
+    pub close_requested: bool,
+}
+
 pub struct Gui {
     pub egui_winit: egui_winit::State,
     renderer: Renderer,
     surface: Arc<Surface>,
+    config: GuiConfig,
 
     shapes: Vec<egui::epaint::ClippedShape>,
     textures_delta: egui::TexturesDelta,
+    repaint_delays: egui::ViewportIdMap<Duration>,
+    viewport_info: egui::ViewportInfo,
+    // Whether `end_frame` was called since the frame began, drawing ends the frame otherwise
+    frame_ended: bool,
+
+    #[cfg(feature = "recording")]
+    recorder: Option<InputRecorder>,
+    #[cfg(feature = "recording")]
+    replay: Option<InputReplay>,
+
+    // Meshes of the last frame and the scale they were tessellated at
+    tessellated_pixels_per_point: f32,
+    clipped_meshes: Vec<ClippedPrimitive>,
+
+    #[cfg(feature = "persistence")]
+    last_save: Instant,
+
+    #[cfg(feature = "image")]
+    texture_loader: Option<Arc<VulkanoTextureLoader>>,
+    #[cfg(feature = "image")]
+    async_image_loads: AsyncImageLoads,
+    #[cfg(feature = "svg")]
+    svg_textures: ahash::AHashMap<egui::TextureId, SvgTexture>,
 }
 
 impl Gui {
@@ -91,13 +227,14 @@ impl Gui {
         theme: Option<winit::window::Theme>,
     ) -> Gui {
         config.validate(output_format);
-        let renderer = Renderer::new_with_render_pass(
+        let mut renderer = Renderer::new_with_render_pass(
             gfx_queue,
             output_format,
             config.is_overlay,
             config.samples,
         );
-        Self::new_internal(event_loop, surface, renderer, theme)
+        renderer.apply_settings(config.renderer_settings());
+        Self::new_internal(event_loop, surface, renderer, config, theme)
     }
 
     /// Same as `new` but instead of integration owning a render pass, egui renders on your subpass
@@ -111,8 +248,9 @@ impl Gui {
         theme: Option<winit::window::Theme>,
     ) -> Gui {
         config.validate(output_format);
-        let renderer = Renderer::new_with_subpass(gfx_queue, output_format, subpass);
-        Self::new_internal(event_loop, surface, renderer, theme)
+        let mut renderer = Renderer::new_with_subpass(gfx_queue, output_format, subpass);
+        renderer.apply_settings(config.renderer_settings());
+        Self::new_internal(event_loop, surface, renderer, config, theme)
     }
 
     /// Same as `new` but instead of integration owning a render pass, egui renders on your subpass
@@ -120,6 +258,7 @@ impl Gui {
         event_loop: &winit::event_loop::ActiveEventLoop,
         surface: Arc<Surface>,
         renderer: Renderer,
+        config: GuiConfig,
         theme: Option<winit::window::Theme>,
     ) -> Gui {
         let max_texture_side =
@@ -135,14 +274,46 @@ impl Gui {
             theme,
             Some(max_texture_side),
         );
-        Gui {
+        let mut viewport_info = egui::ViewportInfo::default();
+        egui_winit::update_viewport_info(
+            &mut viewport_info,
+            egui_winit.egui_ctx(),
+            surface_window(&surface),
+            true,
+        );
+        let gui = Gui {
             // egui_ctx,
             egui_winit,
             renderer,
             surface,
+            config,
             shapes: vec![],
             textures_delta: Default::default(),
+            repaint_delays: Default::default(),
+            viewport_info,
+            frame_ended: false,
+            #[cfg(feature = "recording")]
+            recorder: None,
+            #[cfg(feature = "recording")]
+            replay: None,
+            tessellated_pixels_per_point: 0.0,
+            clipped_meshes: vec![],
+            #[cfg(feature = "persistence")]
+            last_save: Instant::now(),
+            #[cfg(feature = "image")]
+            texture_loader: None,
+            #[cfg(feature = "image")]
+            async_image_loads: Default::default(),
+            #[cfg(feature = "svg")]
+            svg_textures: Default::default(),
+        };
+        #[cfg(feature = "persistence")]
+        if let Some(path) = gui.config.persistence_path.as_ref().filter(|path| path.exists()) {
+            if let Err(err) = gui.load_state(path) {
+                log_error!("Failed to load egui state from {}: {}", path.display(), err);
+            }
         }
+        gui
     }
 
     /// return egui context
@@ -173,13 +344,75 @@ impl Gui {
         window: &Window,
         winit_event: &winit::event::WindowEvent,
     ) -> EventResponse {
+        #[cfg(feature = "persistence")]
+        if matches!(winit_event, winit::event::WindowEvent::CloseRequested) {
+            self.auto_save();
+        }
         self.egui_winit.on_window_event(window, winit_event)
     }
 
+    /// Enables screen reader support by creating an AccessKit adapter for the window.
+    /// Must be called before the window is first shown, so create the window as invisible.
+    /// AccessKit events are sent through `event_loop_proxy` and must be passed to
+    /// `on_accesskit_event`.
+    #[cfg(feature = "accesskit")]
+    pub fn init_accesskit<T: From<accesskit_winit::Event> + Send>(
+        &mut self,
+        event_loop_proxy: EventLoopProxy<T>,
+    ) {
+        self.egui_winit.init_accesskit(surface_window(&self.surface), event_loop_proxy);
+    }
+
+    /// Updates context state by an AccessKit event received through the event loop proxy
+    /// given to `init_accesskit`.
+    #[cfg(feature = "accesskit")]
+    pub fn on_accesskit_event(&mut self, event: &accesskit_winit::Event) {
+        let window = surface_window(&self.surface);
+        if event.window_id != window.id() {
+            return;
+        }
+        match &event.window_event {
+            accesskit_winit::WindowEvent::InitialTreeRequested => {
+                self.egui_ctx().enable_accesskit();
+                // The tree is sent with the platform output of the next frame
+                window.request_redraw();
+            }
+            accesskit_winit::WindowEvent::ActionRequested(request) => {
+                self.egui_winit.on_accesskit_action_request(request.clone());
+                window.request_redraw();
+            }
+            accesskit_winit::WindowEvent::AccessibilityDeactivated => {
+                self.egui_ctx().disable_accesskit();
+            }
+        }
+    }
+
+    /// Updates context state by winit device event. Forwards raw mouse motion, which keeps arriving
+    /// while the cursor is grabbed, to egui's `PointerState::motion`.
+    /// Returns `consumed: true` if egui wants the pointer and `GuiConfig::consume_raw_mouse_motion`
+    /// is set.
+    pub fn update_device_event(
+        &mut self,
+        device_event: &winit::event::DeviceEvent,
+    ) -> EventResponse {
+        let winit::event::DeviceEvent::MouseMotion { delta } = device_event else {
+            return EventResponse { consumed: false, repaint: false };
+        };
+        self.egui_winit.on_mouse_motion(*delta);
+        let wants_pointer = self.egui_ctx().wants_pointer_input();
+        EventResponse {
+            consumed: wants_pointer && self.config.consume_raw_mouse_motion,
+            repaint: wants_pointer,
+        }
+    }
+
     /// Begins Egui frame & determines what will be drawn later. This must be called before draw, and after `update` (winit event).
     pub fn immediate_ui(&mut self, layout_function: impl FnOnce(&mut Self)) {
-        let raw_input = self.egui_winit.take_egui_input(surface_window(&self.surface));
+        profile_scope!("Gui::immediate_ui");
+        self.update_user_textures();
+        let raw_input = self.take_egui_input();
         self.egui_ctx().begin_pass(raw_input);
+        self.frame_ended = false;
         // Render Egui
         layout_function(self);
     }
@@ -187,19 +420,119 @@ impl Gui {
     /// If you wish to better control when to begin frame, do so by calling this function
     /// (Finish by drawing)
     pub fn begin_frame(&mut self) {
-        let raw_input = self.egui_winit.take_egui_input(surface_window(&self.surface));
+        self.update_user_textures();
+        let raw_input = self.take_egui_input();
         self.egui_ctx().begin_pass(raw_input);
+        self.frame_ended = false;
+    }
+
+    /// Takes the accumulated input along with up to date viewport info (size, position, focus,
+    /// fullscreen, monitor etc.) of the window
+    fn take_egui_input(&mut self) -> egui::RawInput {
+        let window = surface_window(&self.surface);
+        egui_winit::update_viewport_info(
+            &mut self.viewport_info,
+            self.egui_winit.egui_ctx(),
+            window,
+            false,
+        );
+        // Events (e.g. a close request) are delivered once
+        let viewport_info = self.viewport_info.clone();
+        self.viewport_info.events.clear();
+        self.egui_winit.egui_input_mut().viewports.insert(egui::ViewportId::ROOT, viewport_info);
+        let raw_input = self.egui_winit.take_egui_input(window);
+        #[cfg(feature = "recording")]
+        let raw_input = self.replay_or_record(raw_input);
+        raw_input
+    }
+
+    /// Replaces live input with the next replayed frame, and records the input that will be used
+    #[cfg(feature = "recording")]
+    fn replay_or_record(&mut self, live_input: egui::RawInput) -> egui::RawInput {
+        let raw_input = match self.replay.as_mut().and_then(|replay| replay.next()) {
+            Some(replayed_input) => replayed_input,
+            None => {
+                self.replay = None;
+                live_input
+            }
+        };
+        if let Some(recorder) = self.recorder.as_mut() {
+            if let Err(err) = recorder.record(&raw_input) {
+                log_error!("Failed to record input, recording stopped: {}", err);
+                self.recorder = None;
+            }
+        }
+        raw_input
+    }
+
+    /// Starts writing every frame's `RawInput` to the file at `path`, see `InputRecorder`
+    #[cfg(feature = "recording")]
+    pub fn start_recording(&mut self, path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        self.recorder = Some(InputRecorder::create(path)?);
+        Ok(())
+    }
+
+    /// Stops recording and flushes the recording file
+    #[cfg(feature = "recording")]
+    pub fn stop_recording(&mut self) -> std::io::Result<()> {
+        match self.recorder.take() {
+            Some(mut recorder) => recorder.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Feeds recorded frames into `begin_frame` instead of live window input until the replay is
+    /// finished. Window events passed to `update` during the replay are discarded.
+    #[cfg(feature = "recording")]
+    pub fn start_replay(&mut self, replay: InputReplay) {
+        self.replay = Some(replay);
+    }
+
+    #[cfg(feature = "recording")]
+    pub fn is_replaying(&self) -> bool {
+        self.replay.is_some()
     }
 
     /// Renders ui on `final_image` & Updates cursor icon
-    /// Finishes Egui frame
-    /// - `before_future` = Vulkano's GpuFuture
+    /// Finishes Egui frame, unless `end_frame` was called
+    /// - `before_future` = Vulkano's GpuFuture, joined with the user texture uploads submitted
+    ///   since the last frame
     /// - `final_image` = Vulkano's image (render target)
+    ///
+    /// The `Error` policies in `GuiConfig` skip like the `Skip` policies here, use
+    /// `try_draw_on_image` to get their errors.
     pub fn draw_on_image<F>(
         &mut self,
         before_future: F,
         final_image: Arc<ImageView>,
     ) -> Box<dyn GpuFuture>
+    where
+        F: GpuFuture + 'static,
+    {
+        self.draw_on_image_internal(before_future, final_image, false)
+            .expect("Only the `Error` policies fail drawing")
+    }
+
+    /// Same as `draw_on_image`, but returns an error if `GuiConfig::missing_texture_policy` or
+    /// `GuiConfig::unsupported_callback_policy` is `Error` and the frame runs into it. Nothing
+    /// is drawn then, and `before_future` is dropped.
+    pub fn try_draw_on_image<F>(
+        &mut self,
+        before_future: F,
+        final_image: Arc<ImageView>,
+    ) -> Result<Box<dyn GpuFuture>, DrawError>
+    where
+        F: GpuFuture + 'static,
+    {
+        self.draw_on_image_internal(before_future, final_image, true)
+    }
+
+    fn draw_on_image_internal<F>(
+        &mut self,
+        before_future: F,
+        final_image: Arc<ImageView>,
+        strict: bool,
+    ) -> Result<Box<dyn GpuFuture>, DrawError>
     where
         F: GpuFuture + 'static,
     {
@@ -210,24 +543,49 @@ impl Gui {
             )
         }
 
-        let (clipped_meshes, textures_delta) = self.extract_draw_data_at_frame_end();
+        let textures_delta = self.extract_draw_data_at_frame_end();
 
         self.renderer.draw_on_image(
-            &clipped_meshes,
+            &self.clipped_meshes,
             &textures_delta,
-            self.pixels_per_point(),
+            self.tessellated_pixels_per_point,
             before_future,
             final_image,
+            strict,
         )
     }
 
     /// Creates commands for rendering ui on subpass' image and returns the command buffer for execution on your side
-    /// - Finishes Egui frame
+    /// - Finishes Egui frame, unless `end_frame` was called
     /// - You must execute the secondary command buffer yourself
+    /// - Waits for the user texture uploads submitted since the last frame, as your command
+    ///   buffer can't be chained after them like in `draw_on_image`
+    ///
+    /// The `Error` policies in `GuiConfig` skip like the `Skip` policies here, use
+    /// `try_draw_on_subpass_image` to get their errors.
     pub fn draw_on_subpass_image(
         &mut self,
         image_dimensions: [u32; 2],
     ) -> Arc<SecondaryAutoCommandBuffer> {
+        self.draw_on_subpass_image_internal(image_dimensions, false)
+            .expect("Only the `Error` policies fail drawing")
+    }
+
+    /// Same as `draw_on_subpass_image`, but returns an error if
+    /// `GuiConfig::missing_texture_policy` or `GuiConfig::unsupported_callback_policy` is `Error`
+    /// and the frame runs into it.
+    pub fn try_draw_on_subpass_image(
+        &mut self,
+        image_dimensions: [u32; 2],
+    ) -> Result<Arc<SecondaryAutoCommandBuffer>, DrawError> {
+        self.draw_on_subpass_image_internal(image_dimensions, true)
+    }
+
+    fn draw_on_subpass_image_internal(
+        &mut self,
+        image_dimensions: [u32; 2],
+        strict: bool,
+    ) -> Result<Arc<SecondaryAutoCommandBuffer>, DrawError> {
         if self.renderer.has_renderpass() {
             panic!(
                 "Gui integration has been created with its own render pass, use `draw_on_image` \
@@ -235,31 +593,114 @@ impl Gui {
             )
         }
 
-        let (clipped_meshes, textures_delta) = self.extract_draw_data_at_frame_end();
+        let textures_delta = self.extract_draw_data_at_frame_end();
 
         self.renderer.draw_on_subpass_image(
-            &clipped_meshes,
+            &self.clipped_meshes,
             &textures_delta,
-            self.pixels_per_point(),
+            self.tessellated_pixels_per_point,
             image_dimensions,
+            strict,
         )
     }
 
-    fn extract_draw_data_at_frame_end(&mut self) -> (Vec<ClippedPrimitive>, TexturesDelta) {
-        self.end_frame();
+    /// Returns what the GUI cost in the last drawn frame: draw counts, texture uploads and texture
+    /// memory. Useful for debug overlays and asserting budgets.
+    pub fn last_frame_stats(&self) -> RenderStats {
+        self.renderer.last_frame_stats()
+    }
+
+    /// Switches the renderer's debug visualization, see `GuiConfig::debug_mode`
+    pub fn set_debug_mode(&mut self, mode: RenderDebugMode) {
+        self.renderer.set_debug_mode(mode);
+        self.config.debug_mode = self.renderer.debug_mode();
+    }
+
+    pub fn debug_mode(&self) -> RenderDebugMode {
+        self.renderer.debug_mode()
+    }
+
+    /// GPU time spent on the GUI pass and each paint callback. Results lag a few frames behind, as
+    /// they're only read once the GPU is done with them. None unless `GuiConfig::gpu_timestamps`
+    /// is set and supported by the queue.
+    pub fn gpu_timings(&self) -> Option<&GpuTimings> {
+        self.renderer.gpu_timings()
+    }
+
+    /// When the app should draw another frame. Zero if the last drawn frame differed from the one
+    /// before it, otherwise the delay egui asked for (zero for e.g. an animation). None if no
+    /// repaint is needed. Unchanged frames are cheap to draw, as their recorded commands are reused.
+    pub fn needs_repaint(&self) -> Option<Duration> {
+        if self.renderer.last_frame_changed() {
+            return Some(Duration::ZERO);
+        }
+        Some(self.repaint_delay()).filter(|delay| *delay != Duration::MAX)
+    }
+
+    /// Delay after which egui wants the window repainted, as of the last finished frame.
+    /// `Duration::MAX` if egui doesn't need a repaint, zero if it should happen immediately.
+    /// During a replay, no later than when the next recorded frame is due.
+    pub fn repaint_delay(&self) -> Duration {
+        let delay = self.viewport_repaint_delay(egui::ViewportId::ROOT).unwrap_or(Duration::MAX);
+        #[cfg(feature = "recording")]
+        if let Some(due) = self.replay.as_ref().and_then(InputReplay::next_frame_due) {
+            return delay.min(due);
+        }
+        delay
+    }
+
+    /// Same as `repaint_delay`, for any viewport in the last finished frame's output
+    pub fn viewport_repaint_delay(&self, viewport_id: egui::ViewportId) -> Option<Duration> {
+        self.repaint_delays.get(&viewport_id).copied()
+    }
+
+    /// Wakes the event loop with a `RequestRepaintEvent` whenever egui requests a repaint,
+    /// including calls to `ctx.request_repaint()` from background threads. This allows using
+    /// `ControlFlow::Wait` instead of redrawing continuously.
+    pub fn set_repaint_proxy<T: From<RequestRepaintEvent> + Send + 'static>(
+        &self,
+        proxy: EventLoopProxy<T>,
+    ) {
+        let proxy = Mutex::new(proxy);
+        self.egui_ctx().set_request_repaint_callback(move |info| {
+            let Some(when) = Instant::now().checked_add(info.delay) else {
+                // Too far in the future to ever matter
+                return;
+            };
+            let event = RequestRepaintEvent { viewport_id: info.viewport_id, when };
+            // Fails only if the event loop no longer exists
+            let _ = proxy.lock().unwrap().send_event(event.into());
+        });
+    }
+
+    /// Finishes the frame, unless `end_frame` already did, and tessellates its shapes into
+    /// `self.clipped_meshes`
+    fn extract_draw_data_at_frame_end(&mut self) -> TexturesDelta {
+        if !self.frame_ended {
+            // Called before drawing by apps that handle close requests
+            let _ = self.end_frame();
+        }
         let shapes = std::mem::take(&mut self.shapes);
         let textures_delta = std::mem::take(&mut self.textures_delta);
-        let clipped_meshes = self.egui_ctx().tessellate(shapes, self.pixels_per_point());
-        (clipped_meshes, textures_delta)
+        let pixels_per_point = self.pixels_per_point();
+        profile_scope!("Gui::tessellate", shapes = shapes.len());
+        self.clipped_meshes = self.egui_ctx().tessellate(shapes, pixels_per_point);
+        self.tessellated_pixels_per_point = pixels_per_point;
+        textures_delta
     }
 
-    fn end_frame(&mut self) {
+    /// Finishes the egui frame: applies its platform output (cursor, clipboard, IME) and its
+    /// viewport commands to the window. Call it after building the UI and before drawing to learn
+    /// whether egui requested to close the window, drawing ends the frame itself otherwise.
+    pub fn end_frame(&mut self) -> FrameOutput {
+        profile_scope!("Gui::end_frame");
+        self.frame_ended = true;
         let egui::FullOutput {
             platform_output,
             textures_delta,
             shapes,
             pixels_per_point: _,
-            viewport_output: _,
+            mut viewport_output,
         } = self.egui_ctx().end_pass();
 
         self.egui_winit.handle_platform_output(
@@ -269,6 +710,137 @@ impl Gui {
         );
         self.shapes = shapes;
         self.textures_delta = textures_delta;
+        self.repaint_delays =
+            viewport_output.iter().map(|(id, output)| (*id, output.repaint_delay)).collect();
+
+        let commands = viewport_output
+            .get_mut(&egui::ViewportId::ROOT)
+            .map(|output| std::mem::take(&mut output.commands))
+            .unwrap_or_default();
+        let output = self.process_viewport_commands(commands);
+
+        #[cfg(feature = "persistence")]
+        if output.close_requested || self.last_save.elapsed() >= self.config.persistence_interval {
+            self.auto_save();
+        }
+        output
+    }
+
+    /// Saves egui's memory (window positions, collapsed headers, panel sizes etc.) to `path`
+    #[cfg(feature = "persistence")]
+    pub fn save_state(&self, path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        crate::persistence::save_memory(self.egui_ctx(), path.as_ref())
+    }
+
+    /// Restores egui's memory saved by `save_state`
+    #[cfg(feature = "persistence")]
+    pub fn load_state(&self, path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        crate::persistence::load_memory(self.egui_ctx(), path.as_ref())
+    }
+
+    /// Saves to `GuiConfig::persistence_path`, if set
+    #[cfg(feature = "persistence")]
+    fn auto_save(&mut self) {
+        self.last_save = Instant::now();
+        let Some(path) = self.config.persistence_path.as_ref() else {
+            return;
+        };
+        if let Err(err) = self.save_state(path) {
+            log_error!("Failed to save egui state to {}: {}", path.display(), err);
+        }
+    }
+
+    /// Applies viewport commands (title, size, fullscreen, cursor grab etc.) sent by
+    /// `ctx.send_viewport_cmd` to the window
+    fn process_viewport_commands(&mut self, commands: Vec<egui::ViewportCommand>) -> FrameOutput {
+        let close_requested = commands.contains(&egui::ViewportCommand::Close)
+            && !commands.contains(&egui::ViewportCommand::CancelClose);
+
+        let mut actions_requested = Default::default();
+        egui_winit::process_viewport_commands(
+            self.egui_winit.egui_ctx(),
+            &mut self.viewport_info,
+            commands,
+            surface_window(&self.surface),
+            &mut actions_requested,
+        );
+        // A close command also queues `ViewportEvent::Close` in `viewport_info.events`. It is left
+        // there on purpose: `take_egui_input` hands it to the next frame, where the UI can see it
+        // through `ctx.input(|i| i.viewport().close_requested())`. The app learns about the close
+        // from the returned `FrameOutput` instead.
+
+        for action in actions_requested {
+            let event = match action {
+                ActionRequested::Cut => egui::Event::Cut,
+                ActionRequested::Copy => egui::Event::Copy,
+                ActionRequested::Paste => match self.egui_winit.clipboard_text() {
+                    Some(text) => egui::Event::Paste(text),
+                    None => continue,
+                },
+                // Screenshots of the render target are not supported
+                ActionRequested::Screenshot(_) => continue,
+            };
+            self.egui_winit.egui_input_mut().events.push(event);
+        }
+        FrameOutput { close_requested }
+    }
+
+    /// Rebuilds all GPU state on the device of `gfx_queue`, e.g. after the previous device was lost,
+    /// and re-uploads every retained texture so that the app can continue with the same egui state.
+    /// Requires `GuiConfig::retain_texture_data`.
+    ///
+    /// User images registered with `register_user_image_view`, inspected images and videos can't
+    /// be restored, nor can retained images the new device fails to upload. Their ids are
+    /// returned and should be registered again.
+    pub fn recreate_on_device(&mut self, gfx_queue: Arc<Queue>) -> Vec<egui::TextureId> {
+        if !self.renderer.has_renderpass() {
+            panic!(
+                "Gui integration has been created with subpass, use \
+                 `recreate_on_device_with_subpass` instead"
+            )
+        }
+        self.recreate_internal(gfx_queue, None)
+    }
+
+    /// Same as `recreate_on_device` but egui renders on your subpass, which must belong to the new
+    /// device.
+    pub fn recreate_on_device_with_subpass(
+        &mut self,
+        gfx_queue: Arc<Queue>,
+        subpass: Subpass,
+    ) -> Vec<egui::TextureId> {
+        if self.renderer.has_renderpass() {
+            panic!(
+                "Gui integration has been created with its own render pass, use \
+                 `recreate_on_device` instead"
+            )
+        }
+        self.recreate_internal(gfx_queue, Some(subpass))
+    }
+
+    fn recreate_internal(
+        &mut self,
+        gfx_queue: Arc<Queue>,
+        subpass: Option<Subpass>,
+    ) -> Vec<egui::TextureId> {
+        assert!(
+            self.renderer.retains_texture_data(),
+            "Recreating on a new device requires `GuiConfig::retain_texture_data` to be set!"
+        );
+        let max_texture_side =
+            gfx_queue.device().physical_device().properties().max_image_dimension2_d as usize;
+        self.egui_winit.set_max_texture_side(max_texture_side);
+        let lost = self.renderer.recreate_on_device(gfx_queue, subpass);
+        #[cfg(feature = "svg")]
+        let lost = self.restore_svgs(lost);
+        lost
+    }
+
+    /// Returns a cloneable handle that registers and unregisters user textures from any thread,
+    /// e.g. from background asset loaders. Queued textures are created at the start of the next
+    /// `draw_on_image` / `draw_on_subpass_image` call.
+    pub fn texture_registrar(&self) -> TextureRegistrar {
+        self.renderer.texture_registrar()
     }
 
     /// Registers a user image from Vulkano image view to be used by egui
@@ -280,6 +852,81 @@ impl Gui {
         self.renderer.register_image(image, sampler_create_info)
     }
 
+    /// Registers an image for debugging tools, shown by an inspector shader instead of as a color
+    /// texture. Works with depth, stencil, integer (e.g. R32_UINT ID buffers), array, cube and 3D
+    /// images; `options` select the aspect, mip level, layer or slice, the channels to show and
+    /// the range of values mapped to black and white. The image must have `ImageUsage::SAMPLED`.
+    pub fn register_user_image_inspector(
+        &mut self,
+        image: Arc<Image>,
+        options: InspectOptions,
+        sampler_create_info: SamplerCreateInfo,
+    ) -> Result<egui::TextureId, InspectError> {
+        self.renderer.register_inspected_image(image, options, sampler_create_info)
+    }
+
+    /// Changes how an image registered with `register_user_image_inspector` is shown, keeping its
+    /// `TextureId`
+    pub fn set_user_image_inspect_options(
+        &mut self,
+        texture_id: egui::TextureId,
+        options: InspectOptions,
+    ) -> Result<(), InspectError> {
+        self.renderer.set_inspect_options(texture_id, options)
+    }
+
+    /// Swaps the image of a user texture for another one, e.g. a viewport image recreated on
+    /// resize, keeping its `TextureId` and sampler
+    pub fn replace_user_image_view(
+        &mut self,
+        texture_id: egui::TextureId,
+        image: Arc<ImageView>,
+    ) -> Result<(), TextureUpdateError> {
+        #[cfg(feature = "svg")]
+        self.svg_textures.remove(&texture_id);
+        self.renderer.replace_image_view(texture_id, image)
+    }
+
+    /// Overwrites a region of a user texture in place with tightly packed texels in the format of
+    /// its image, e.g. to stream CPU-generated data. The image must have been created with
+    /// `ImageUsage::TRANSFER_DST`, which images registered from bytes or files are. The copy is
+    /// submitted without waiting for it, the next draw waits for it instead.
+    /// - `offset`, `extent`: region in pixels, aligned to the blocks of compressed formats
+    pub fn update_user_image_region(
+        &mut self,
+        texture_id: egui::TextureId,
+        data: &[u8],
+        offset: [u32; 2],
+        extent: [u32; 2],
+    ) -> Result<(), TextureUpdateError> {
+        self.renderer.update_image_region(texture_id, data, offset, extent)
+    }
+
+    /// Registers a texture showing decoded YUV 4:2:0 video, e.g. from a hardware decoder. Frames
+    /// are converted to RGB by a YCbCr sampler when the device has the `sampler_ycbcr_conversion`
+    /// feature enabled and supports the format, otherwise by a shader.
+    /// - `extent`: frame size in pixels, which must be even
+    pub fn register_user_video(
+        &mut self,
+        format: YuvFormat,
+        extent: [u32; 2],
+        color_space: YuvColorSpace,
+        range: YuvRange,
+    ) -> Result<egui::TextureId, VideoError> {
+        self.renderer.register_video(format, extent, color_space, range)
+    }
+
+    /// Uploads the next frame of a video registered with `register_user_video`, one
+    /// `VideoPlane` per plane of its format (Y and UV for NV12, Y, U and V for I420). Fails with
+    /// `VideoError::SlotInUse` if more frames are in flight than `GuiConfig::frames_in_flight`.
+    pub fn update_user_video_frame(
+        &mut self,
+        texture_id: egui::TextureId,
+        planes: &[VideoPlane<'_>],
+    ) -> Result<(), VideoError> {
+        self.renderer.update_video_frame(texture_id, planes)
+    }
+
     /// Registers a user image to be used by egui
     /// - `image_file_bytes`: e.g. include_bytes!("./assets/tree.png")
     /// - `format`: e.g. vulkano::format::Format::R8G8B8A8Unorm
@@ -290,14 +937,82 @@ impl Gui {
         format: vulkano::format::Format,
         sampler_create_info: SamplerCreateInfo,
     ) -> egui::TextureId {
-        let image = immutable_texture_from_file(
-            self.renderer.allocators(),
-            self.renderer.queue(),
-            image_file_bytes,
+        self.renderer.register_image_from_file(image_file_bytes, format, sampler_create_info)
+    }
+
+    /// Registers an already decoded image to be used by egui. Grayscale, 16-bit and float images
+    /// are uploaded in a matching format, `format` applies to 8-bit RGB(A) images.
+    /// - `format`: e.g. vulkano::format::Format::R8G8B8A8_SRGB
+    #[cfg(feature = "image")]
+    pub fn register_user_image_from_dynamic(
+        &mut self,
+        image: &image::DynamicImage,
+        format: vulkano::format::Format,
+        sampler_create_info: SamplerCreateInfo,
+    ) -> egui::TextureId {
+        self.renderer.register_image_from_dynamic(image, format, sampler_create_info)
+    }
+
+    /// Loads an image file (from a path or encoded bytes) without blocking: returns its id right
+    /// away and decodes it on a worker thread. Until then `GuiConfig::loading_placeholder` is
+    /// shown; `user_image_load_state` tells how loading went. The decoded image is uploaded at the
+    /// start of the next draw, like images registered through a `TextureRegistrar`.
+    /// - `format`: e.g. vulkano::format::Format::R8G8B8A8_SRGB
+    #[cfg(feature = "image")]
+    pub fn load_user_image_async(
+        &mut self,
+        source: impl Into<ImageSource>,
+        format: vulkano::format::Format,
+        sampler_create_info: SamplerCreateInfo,
+    ) -> egui::TextureId {
+        let registrar = self.renderer.texture_registrar();
+        let id = registrar.allocate_id();
+        if let Some(color) = self.config.loading_placeholder {
+            registrar.queue_bytes(
+                id,
+                color.to_srgba_unmultiplied().to_vec(),
+                [1, 1],
+                vulkano::format::Format::R8G8B8A8_SRGB,
+                sampler_create_info.clone(),
+            );
+        }
+        self.async_image_loads.spawn(
+            self.context(),
+            registrar,
+            id,
+            source.into(),
             format,
-        )
-        .expect("Failed to create image");
-        self.renderer.register_image(image, sampler_create_info)
+            sampler_create_info,
+        );
+        id
+    }
+
+    /// Load state of an image from `load_user_image_async`, None for other (or unregistered) ids
+    #[cfg(feature = "image")]
+    pub fn user_image_load_state(&self, texture_id: egui::TextureId) -> Option<ImageLoadState> {
+        self.async_image_loads.state(texture_id)
+    }
+
+    /// Decodes all frames of an animated GIF, APNG or WebP and uploads them once into an atlas
+    /// texture. Draw it with `AnimatedTexture::image(elapsed)`, which picks the frame's UV rect.
+    /// Still images become a single frame animation. Fails with a limit error if the frames don't
+    /// fit into a texture of the device's maximum size.
+    #[cfg(feature = "image")]
+    pub fn register_user_animation(
+        &mut self,
+        image_file_bytes: &[u8],
+        sampler_create_info: SamplerCreateInfo,
+    ) -> Result<AnimatedTexture, image::ImageError> {
+        let max_side =
+            self.renderer.queue().device().physical_device().properties().max_image_dimension2_d;
+        let atlas = AnimationAtlas::decode(image_file_bytes, max_side)?;
+        let id = self.renderer.register_image_from_bytes(
+            atlas.pixels.as_raw(),
+            [atlas.pixels.width(), atlas.pixels.height()],
+            vulkano::format::Format::R8G8B8A8_SRGB,
+            sampler_create_info,
+        );
+        Ok(atlas.into_texture(id))
     }
 
     pub fn register_user_image_from_bytes(
@@ -307,20 +1022,164 @@ impl Gui {
         format: vulkano::format::Format,
         sampler_create_info: SamplerCreateInfo,
     ) -> egui::TextureId {
-        let image = immutable_texture_from_bytes(
-            self.renderer.allocators(),
-            self.renderer.queue(),
+        self.renderer.register_image_from_bytes(
             image_byte_data,
             dimensions,
             format,
+            sampler_create_info,
         )
-        .expect("Failed to create image");
-        self.renderer.register_image(image, sampler_create_info)
+    }
+
+    /// Registers a KTX2 texture (BCn, ETC2, ASTC or uncompressed) with all its mip levels.
+    /// Formats the device can't sample are decompressed to RGBA8 on the CPU where possible.
+    #[cfg(feature = "ktx2")]
+    pub fn register_user_image_ktx2(
+        &mut self,
+        file_bytes: &[u8],
+        sampler_create_info: SamplerCreateInfo,
+    ) -> Result<egui::TextureId, ImageCreationError> {
+        self.renderer.register_image_from_ktx2(file_bytes, sampler_create_info)
+    }
+
+    /// Registers a DDS texture (BC1-7 or 8-bit RGBA / BGRA) with all its mip levels.
+    /// Formats the device can't sample are decompressed to RGBA8 on the CPU where possible.
+    #[cfg(feature = "dds")]
+    pub fn register_user_image_dds(
+        &mut self,
+        file_bytes: &[u8],
+        sampler_create_info: SamplerCreateInfo,
+    ) -> Result<egui::TextureId, ImageCreationError> {
+        self.renderer.register_image_from_dds(file_bytes, sampler_create_info)
+    }
+
+    /// Installs a texture loader for `ui.image(..)` & co. that decodes images (from `bytes://`,
+    /// `file://` or any uri egui's bytes loaders support) and uploads them straight into vulkano
+    /// textures, bypassing egui's managed textures. Each uri & texture options pair is uploaded
+    /// once, `egui::Context::forget_image` frees its texture again.
+    #[cfg(feature = "image")]
+    pub fn install_texture_loader(&mut self) {
+        if self.texture_loader.is_none() {
+            let loader = Arc::new(VulkanoTextureLoader::new(self.async_image_loads.pool()));
+            self.egui_ctx().add_texture_loader(loader.clone());
+            self.texture_loader = Some(loader);
+        }
+    }
+
+    /// Brings user textures up to date before the frame's layout uses them
+    fn update_user_textures(&mut self) {
+        #[cfg(feature = "image")]
+        self.upload_loaded_textures();
+        #[cfg(feature = "svg")]
+        self.rasterize_svgs();
+    }
+
+    /// Uploads images the texture loader decoded during the last frame, and frees the textures of
+    /// forgotten ones
+    #[cfg(feature = "image")]
+    fn upload_loaded_textures(&mut self) {
+        let Some(loader) = &self.texture_loader else {
+            return;
+        };
+        for id in loader.take_freed() {
+            self.renderer.unregister_image(id);
+        }
+        for upload in loader.take_uploads() {
+            let byte_size = upload.image.as_bytes().len();
+            let id = self.renderer.register_owned_image(
+                upload.image,
+                vulkano::format::Format::R8G8B8A8_SRGB,
+                texture_loader::sampler_create_info(upload.key.1),
+            );
+            if !loader.finish_upload(upload.key, id, byte_size) {
+                self.renderer.unregister_image(id);
+            }
+        }
+    }
+
+    /// Rasterizes an SVG and registers it to be used by egui. Unless `size` is in pixels, the SVG
+    /// is rasterized again whenever `pixels_per_point` changes, so it stays crisp on HiDPI
+    /// displays. The texture keeps its id through that.
+    #[cfg(feature = "svg")]
+    pub fn register_user_svg(
+        &mut self,
+        svg_data: &[u8],
+        size: SvgSize,
+        sampler_create_info: SamplerCreateInfo,
+    ) -> Result<egui::TextureId, SvgError> {
+        let mut svg = SvgTexture::parse(svg_data, size, sampler_create_info)?;
+        let (pixels, dimensions) = svg.rasterize(self.pixels_per_point())?;
+        let id = self.renderer.register_image_from_bytes(
+            &pixels,
+            dimensions,
+            vulkano::format::Format::R8G8B8A8_SRGB,
+            svg.sampler_create_info.clone(),
+        );
+        self.svg_textures.insert(id, svg);
+        Ok(id)
+    }
+
+    /// Rasterizes SVGs again that were rasterized at another scale. Their textures keep their
+    /// samplers.
+    #[cfg(feature = "svg")]
+    fn rasterize_svgs(&mut self) {
+        let pixels_per_point = self.pixels_per_point();
+        for (&id, svg) in &mut self.svg_textures {
+            if !svg.needs_rasterize(pixels_per_point) {
+                continue;
+            }
+            let (pixels, dimensions) = match svg.rasterize(pixels_per_point) {
+                Ok(rasterized) => rasterized,
+                Err(err) => {
+                    log_warn!("Failed to rasterize SVG {:?}: {:?}", id, err);
+                    continue;
+                }
+            };
+            match self.renderer.create_image_from_bytes(&pixels, dimensions, Format::R8G8B8A8_SRGB)
+            {
+                // Fails only if the texture was unregistered from the renderer directly
+                Ok(image) => {
+                    let _ = self.renderer.replace_image_view(id, image);
+                }
+                Err(err) => log_warn!("Failed to upload SVG {:?}: {:?}", id, err),
+            }
+        }
+    }
+
+    /// Rasterizes the SVGs among `lost` textures again on the renderer's new device, returns
+    /// the textures that are still lost
+    #[cfg(feature = "svg")]
+    fn restore_svgs(&mut self, mut lost: Vec<egui::TextureId>) -> Vec<egui::TextureId> {
+        let pixels_per_point = self.pixels_per_point();
+        lost.retain(|id| {
+            let Some(svg) = self.svg_textures.get_mut(id) else {
+                return true;
+            };
+            let image = svg.rasterize(pixels_per_point).ok().and_then(|(pixels, dimensions)| {
+                self.renderer
+                    .create_image_from_bytes(&pixels, dimensions, Format::R8G8B8A8_SRGB)
+                    .ok()
+            });
+            match image {
+                Some(image) => {
+                    self.renderer.insert_image(*id, image, svg.sampler_create_info.clone());
+                    false
+                }
+                None => true,
+            }
+        });
+        lost
     }
 
     /// Unregisters a user image
     pub fn unregister_user_image(&mut self, texture_id: egui::TextureId) {
         self.renderer.unregister_image(texture_id);
+        // The decoded image may already be queued, unregister again after it
+        #[cfg(feature = "image")]
+        if self.async_image_loads.remove(texture_id) {
+            self.renderer.texture_registrar().unregister_image(texture_id);
+        }
+        #[cfg(feature = "svg")]
+        self.svg_textures.remove(&texture_id);
     }
 
     /// Access egui's context (which can be used to e.g. set fonts, visuals etc)
@@ -7,13 +7,54 @@
 // notice may not be copied, modified, or distributed except
 // according to those terms.
 
+#[cfg(feature = "image")]
+mod animation;
+#[cfg(feature = "image")]
+mod async_image;
+#[cfg(any(feature = "ktx2", feature = "dds"))]
+mod compressed;
+mod diagnostics;
+mod inspect;
 mod integration;
+#[cfg(feature = "persistence")]
+mod persistence;
+mod profiling;
+#[cfg(feature = "recording")]
+mod recording;
+mod registrar;
 mod renderer;
+#[cfg(feature = "svg")]
+mod svg;
+#[cfg(feature = "image")]
+mod texture_loader;
+mod timestamps;
 mod utils;
+mod video;
 
+#[cfg(feature = "image")]
+pub use animation::AnimatedTexture;
+#[cfg(feature = "image")]
+pub use async_image::{ImageLoadState, ImageSource};
+#[cfg(feature = "dds")]
+pub use compressed::immutable_texture_from_dds;
+#[cfg(feature = "ktx2")]
+pub use compressed::immutable_texture_from_ktx2;
 pub use egui;
+#[cfg(feature = "accesskit")]
+pub use egui_winit::accesskit_winit;
+pub use inspect::{InspectAspect, InspectChannel, InspectError, InspectOptions};
 pub use integration::*;
-pub use renderer::{CallbackContext, CallbackFn, RenderResources};
-pub use utils::immutable_texture_from_bytes;
+#[cfg(feature = "recording")]
+pub use recording::{InputRecorder, InputReplay, RecordedFrame};
+pub use registrar::{RegistrationError, TextureRegistrar};
+pub use renderer::{
+    CallbackContext, CallbackFn, DrawError, MissingTexturePolicy, RenderDebugMode, RenderResources,
+    RenderStats, TextureUpdateError, UnsupportedCallbackPolicy,
+};
+#[cfg(feature = "svg")]
+pub use svg::{SvgError, SvgSize};
+pub use timestamps::GpuTimings;
+pub use utils::{immutable_texture_from_bytes, ImageCreationError};
 #[cfg(feature = "image")]
-pub use utils::immutable_texture_from_file;
+pub use utils::{immutable_texture_from_dynamic, immutable_texture_from_file};
+pub use video::{VideoError, VideoPlane, YuvColorSpace, YuvFormat, YuvRange};
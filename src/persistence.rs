@@ -0,0 +1,37 @@
+// Copyright (c) 2021 Okko Hakola
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+};
+
+/// Writes egui's memory (window positions, collapsed headers, panel sizes etc.) as JSON to `path`.
+/// The file is replaced only once fully written.
+pub fn save_memory(ctx: &egui::Context, path: &Path) -> std::io::Result<()> {
+    // Appended rather than replacing the extension, so that e.g. `gui.json` and `gui.ron` don't
+    // share a temporary file
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    {
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        ctx.memory(|memory| serde_json::to_writer(&mut writer, memory))?;
+        writer.flush()?;
+    }
+    std::fs::rename(tmp_path, path)
+}
+
+/// Replaces egui's memory with the one saved at `path` by `save_memory`
+pub fn load_memory(ctx: &egui::Context, path: &Path) -> std::io::Result<()> {
+    let reader = BufReader::new(File::open(path)?);
+    let memory: egui::Memory = serde_json::from_reader(reader)?;
+    ctx.memory_mut(|current| *current = memory);
+    Ok(())
+}
@@ -0,0 +1,25 @@
+// Copyright (c) 2021 Okko Hakola
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+/// Profiles the rest of the enclosing block as a `tracing` span and/or a `puffin` scope, depending
+/// on which of those features are enabled. Does nothing otherwise. `key = value` pairs are
+/// recorded as span fields and as the scope's data.
+macro_rules! profile_scope {
+    ($name:literal $(, $key:ident = $value:expr)* $(,)?) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!($name $(, $key = $value)*).entered();
+        #[cfg(feature = "puffin")]
+        puffin::profile_scope!(
+            $name,
+            format!(concat!($(stringify!($key), "={} "),*), $($value),*).trim_end()
+        );
+    };
+}
+
+pub(crate) use profile_scope;
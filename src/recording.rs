@@ -0,0 +1,222 @@
+// Copyright (c) 2021 Okko Hakola
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Input of a single recorded frame
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    /// Time since the recording was started
+    pub elapsed: Duration,
+    pub input: egui::RawInput,
+}
+
+/// Writes the `RawInput` of every frame to a file, one JSON encoded `RecordedFrame` per line.
+pub struct InputRecorder {
+    writer: BufWriter<File>,
+    start: Instant,
+}
+
+impl InputRecorder {
+    /// Creates (or truncates) the recording file at `path`
+    pub fn create(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(Self { writer: BufWriter::new(File::create(path)?), start: Instant::now() })
+    }
+
+    pub fn record(&mut self, input: &egui::RawInput) -> std::io::Result<()> {
+        let frame = RecordedFrame { elapsed: self.start.elapsed(), input: input.clone() };
+        serde_json::to_writer(&mut self.writer, &frame)?;
+        self.writer.write_all(b"\n")
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Recorded frames to be fed back into egui frame-for-frame. Besides `Gui::start_replay`, this can
+/// be iterated directly to drive a headless `egui::Context` in tests.
+///
+/// Every taken frame yields exactly one recorded frame, however fast they are taken. To replay at
+/// the recorded pace, draw the next frame after `next_frame_due`, which `Gui::repaint_delay`
+/// takes into account during a replay.
+#[derive(Clone, Debug, Default)]
+pub struct InputReplay {
+    frames: VecDeque<RecordedFrame>,
+    // When the recording would have started, had it been recorded alongside this replay
+    start: Option<Instant>,
+}
+
+impl InputReplay {
+    /// Reads a recording written by `InputRecorder`
+    pub fn from_file(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut frames = VecDeque::new();
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            frames.push_back(serde_json::from_str(&line)?);
+        }
+        Ok(Self::from_frames(frames))
+    }
+
+    pub fn from_frames(frames: impl IntoIterator<Item = RecordedFrame>) -> Self {
+        Self { frames: frames.into_iter().collect(), start: None }
+    }
+
+    /// Number of frames left to replay
+    pub fn remaining(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Takes the next recorded frame
+    pub fn next_frame(&mut self) -> Option<RecordedFrame> {
+        let frame = self.frames.pop_front()?;
+        // The first replayed frame sets the pace for the rest
+        if self.start.is_none() {
+            let now = Instant::now();
+            self.start = Some(now.checked_sub(frame.elapsed).unwrap_or(now));
+        }
+        Some(frame)
+    }
+
+    /// Time until the next frame is due at the recorded pace, zero if it is late. Before the first
+    /// frame is taken it is always due. None once the replay is finished.
+    pub fn next_frame_due(&self) -> Option<Duration> {
+        let frame = self.frames.front()?;
+        let Some(start) = self.start else {
+            return Some(Duration::ZERO);
+        };
+        Some((start + frame.elapsed).saturating_duration_since(Instant::now()))
+    }
+}
+
+impl Iterator for InputReplay {
+    type Item = egui::RawInput;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_frame().map(|frame| frame.input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use egui::{pos2, vec2, Event, Modifiers, PointerButton, Pos2, RawInput, Rect};
+
+    use super::*;
+
+    /// Runs one frame of a UI with a single button, returns the button's rect
+    fn run_frame(ctx: &egui::Context, input: RawInput, clicks: &mut usize) -> Rect {
+        let mut rect = Rect::NOTHING;
+        let _ = ctx.run(input, |ctx| {
+            egui::CentralPanel::default().show(ctx, |ui| {
+                let response = ui.button("Click me");
+                if response.clicked() {
+                    *clicks += 1;
+                }
+                rect = response.rect;
+            });
+        });
+        rect
+    }
+
+    fn input(events: Vec<Event>) -> RawInput {
+        RawInput {
+            screen_rect: Some(Rect::from_min_size(Pos2::ZERO, vec2(400.0, 300.0))),
+            events,
+            ..Default::default()
+        }
+    }
+
+    fn click_at(pos: Pos2) -> Vec<RawInput> {
+        let button = |pressed| Event::PointerButton {
+            pos,
+            button: PointerButton::Primary,
+            pressed,
+            modifiers: Modifiers::default(),
+        };
+        vec![
+            input(vec![Event::PointerMoved(pos)]),
+            input(vec![button(true)]),
+            input(vec![button(false)]),
+        ]
+    }
+
+    #[test]
+    fn replays_recorded_click_headless() {
+        let path = std::env::temp_dir()
+            .join(format!("egui_winit_vulkano_replay_{}.jsonl", std::process::id()));
+
+        // Record a click on the button
+        let live = egui::Context::default();
+        let mut live_clicks = 0;
+        let mut recorder = InputRecorder::create(&path).unwrap();
+        let first = input(vec![]);
+        recorder.record(&first).unwrap();
+        let button = run_frame(&live, first, &mut live_clicks);
+        for frame in click_at(button.center()) {
+            recorder.record(&frame).unwrap();
+            run_frame(&live, frame, &mut live_clicks);
+        }
+        recorder.flush().unwrap();
+        assert_eq!(live_clicks, 1);
+
+        // Replaying into a fresh context clicks the button again
+        let replay = InputReplay::from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(replay.remaining(), 4);
+        let replayed = egui::Context::default();
+        let mut replayed_clicks = 0;
+        for frame in replay {
+            run_frame(&replayed, frame, &mut replayed_clicks);
+        }
+        assert_eq!(replayed_clicks, 1);
+    }
+
+    #[test]
+    fn replay_stays_frame_for_frame() {
+        let frames = click_at(pos2(10.0, 10.0))
+            .into_iter()
+            .map(|input| RecordedFrame { elapsed: Duration::ZERO, input })
+            .collect::<Vec<_>>();
+        let replay = InputReplay::from_frames(frames.clone());
+        let inputs = replay.collect::<Vec<_>>();
+        assert_eq!(inputs, frames.into_iter().map(|frame| frame.input).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn next_frame_due_follows_recorded_pace() {
+        let frame = |elapsed| RecordedFrame { elapsed, input: input(vec![]) };
+        let mut replay = InputReplay::from_frames([
+            frame(Duration::from_secs(5)),
+            frame(Duration::from_secs(3605)),
+        ]);
+        assert_eq!(replay.next_frame_due(), Some(Duration::ZERO));
+        replay.next_frame().unwrap();
+        let due = replay.next_frame_due().unwrap();
+        assert!(due > Duration::from_secs(3599) && due <= Duration::from_secs(3600));
+        replay.next_frame().unwrap();
+        assert_eq!(replay.next_frame_due(), None);
+        assert!(replay.is_finished());
+    }
+}
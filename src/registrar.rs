@@ -0,0 +1,186 @@
+// Copyright (c) 2021 Okko Hakola
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use egui::TextureId;
+use vulkano::{format::Format, image::sampler::SamplerCreateInfo, image::view::ImageView};
+
+#[cfg(feature = "image")]
+use crate::utils::ImageCreationError;
+
+/// Called with the result of uploading a queued image, on the rendering thread
+#[cfg(feature = "image")]
+pub(crate) type UploadCallback = Box<dyn FnOnce(Result<(), ImageCreationError>) + Send>;
+
+/// A registration queued by a `TextureRegistrar`, applied by the renderer before its next draw
+pub(crate) enum PendingRegistration {
+    Bytes {
+        id: TextureId,
+        data: Vec<u8>,
+        dimensions: [u32; 2],
+        format: Format,
+        sampler_create_info: SamplerCreateInfo,
+    },
+    #[cfg(feature = "image")]
+    Dynamic {
+        id: TextureId,
+        image: image::DynamicImage,
+        format: Format,
+        sampler_create_info: SamplerCreateInfo,
+        on_uploaded: UploadCallback,
+    },
+    View {
+        id: TextureId,
+        image: Arc<ImageView>,
+        sampler_create_info: SamplerCreateInfo,
+    },
+    Unregister(TextureId),
+}
+
+/// Why `TextureRegistrar::register_image_from_bytes` rejected an image
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegistrationError {
+    /// The image has zero width or height
+    InvalidDimensions([u32; 2]),
+    /// The data isn't the size of the tightly packed image in its format
+    DataSize { expected: usize, actual: usize },
+}
+
+impl fmt::Display for RegistrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistrationError::InvalidDimensions(dimensions) => {
+                write!(f, "invalid image dimensions {:?}", dimensions)
+            }
+            RegistrationError::DataSize { expected, actual } => {
+                write!(f, "expected {} bytes of data, got {}", expected, actual)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RegistrationError {}
+
+#[derive(Default)]
+struct Shared {
+    next_id: AtomicU64,
+    pending: Mutex<Vec<PendingRegistration>>,
+}
+
+/// Handle for registering user textures from any thread, obtained with `Gui::texture_registrar`.
+///
+/// Ids are allocated immediately, so they can be handed to the UI right away, while the
+/// textures themselves are created at the start of the next `draw_*` call. Until then, drawing
+/// an id is handled like any missing texture (see `GuiConfig::missing_texture_policy`).
+/// Registrations are applied in the order they were queued.
+#[derive(Clone)]
+pub struct TextureRegistrar {
+    shared: Arc<Shared>,
+}
+
+impl TextureRegistrar {
+    /// Created by the renderer, handed out by `Gui::texture_registrar`
+    pub(crate) fn new() -> Self {
+        Self { shared: Arc::default() }
+    }
+
+    /// Allocates a new user texture id. Shared with the renderer, so ids never collide with
+    /// textures registered through `Gui`.
+    pub fn allocate_id(&self) -> TextureId {
+        TextureId::User(self.shared.next_id.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Queues tightly packed pixels to be uploaded as a new user texture. The data size is
+    /// checked right away, so that the upload doesn't fail later on the rendering thread.
+    pub fn register_image_from_bytes(
+        &self,
+        image_byte_data: Vec<u8>,
+        dimensions: [u32; 2],
+        format: Format,
+        sampler_create_info: SamplerCreateInfo,
+    ) -> Result<TextureId, RegistrationError> {
+        if dimensions.contains(&0) {
+            return Err(RegistrationError::InvalidDimensions(dimensions));
+        }
+        let [block_width, block_height, _] = format.block_extent();
+        let expected = dimensions[0].div_ceil(block_width) as usize
+            * dimensions[1].div_ceil(block_height) as usize
+            * format.block_size() as usize;
+        if image_byte_data.len() != expected {
+            return Err(RegistrationError::DataSize { expected, actual: image_byte_data.len() });
+        }
+        let id = self.allocate_id();
+        self.queue_bytes(id, image_byte_data, dimensions, format, sampler_create_info);
+        Ok(id)
+    }
+
+    /// Queues an image view, which must belong to the renderer's device, as a new user texture
+    pub fn register_image_view(
+        &self,
+        image: Arc<ImageView>,
+        sampler_create_info: SamplerCreateInfo,
+    ) -> TextureId {
+        let id = self.allocate_id();
+        self.push(PendingRegistration::View { id, image, sampler_create_info });
+        id
+    }
+
+    /// Queues unregistering a user texture, whether it was registered through this handle or not
+    pub fn unregister_image(&self, id: TextureId) {
+        self.push(PendingRegistration::Unregister(id));
+    }
+
+    /// Queues pixels for an already allocated id, replacing its texture if it has one
+    pub(crate) fn queue_bytes(
+        &self,
+        id: TextureId,
+        data: Vec<u8>,
+        dimensions: [u32; 2],
+        format: Format,
+        sampler_create_info: SamplerCreateInfo,
+    ) {
+        self.push(PendingRegistration::Bytes { id, data, dimensions, format, sampler_create_info });
+    }
+
+    /// Queues a decoded image for an already allocated id, replacing its texture if it has one.
+    /// `on_uploaded` is called once the renderer has tried to upload it.
+    #[cfg(feature = "image")]
+    pub(crate) fn queue_dynamic(
+        &self,
+        id: TextureId,
+        image: image::DynamicImage,
+        format: Format,
+        sampler_create_info: SamplerCreateInfo,
+        on_uploaded: UploadCallback,
+    ) {
+        self.push(PendingRegistration::Dynamic {
+            id,
+            image,
+            format,
+            sampler_create_info,
+            on_uploaded,
+        });
+    }
+
+    fn push(&self, registration: PendingRegistration) {
+        self.shared.pending.lock().unwrap().push(registration);
+    }
+
+    /// Takes the registrations queued since the last call
+    pub(crate) fn take_pending(&self) -> Vec<PendingRegistration> {
+        std::mem::take(&mut self.shared.pending.lock().unwrap())
+    }
+}
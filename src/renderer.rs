@@ -7,36 +7,42 @@
 // notice may not be copied, modified, or distributed except
 // according to those terms.
 
-use std::sync::Arc;
+use std::{
+    borrow::Cow,
+    fmt,
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
 
-use ahash::AHashMap;
+use ahash::{AHashMap, AHasher};
 use egui::{epaint::Primitive, ClippedPrimitive, PaintCallbackInfo, Rect, TexturesDelta};
 use vulkano::{
     buffer::{
         allocator::{SubbufferAllocator, SubbufferAllocatorCreateInfo},
-        Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer,
+        AllocateBufferError, Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer,
     },
     command_buffer::{
         allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, BufferImageCopy,
-        CommandBufferInheritanceInfo, CommandBufferUsage, CopyBufferToImageInfo,
-        PrimaryAutoCommandBuffer, PrimaryCommandBufferAbstract, RenderPassBeginInfo,
-        SecondaryAutoCommandBuffer, SubpassBeginInfo, SubpassContents,
+        ClearAttachment, ClearRect, CommandBufferInheritanceInfo, CommandBufferUsage,
+        CopyBufferToImageInfo, PrimaryAutoCommandBuffer, PrimaryCommandBufferAbstract,
+        RenderPassBeginInfo, SecondaryAutoCommandBuffer, SubpassBeginInfo, SubpassContents,
     },
     descriptor_set::{
         allocator::StandardDescriptorSetAllocator, layout::DescriptorSetLayout, DescriptorSet,
         WriteDescriptorSet,
     },
-    device::Queue,
-    format::{Format, NumericFormat},
+    device::{DeviceOwned, Queue},
+    format::{ClearColorValue, Format, NumericFormat},
     image::{
         sampler::{
-            ComponentMapping, ComponentSwizzle, Filter, Sampler, SamplerAddressMode,
-            SamplerCreateInfo, SamplerMipmapMode,
+            ycbcr::SamplerYcbcrConversion, ComponentMapping, ComponentSwizzle, Filter, Sampler,
+            SamplerAddressMode, SamplerCreateInfo, SamplerMipmapMode,
         },
         view::{ImageView, ImageViewCreateInfo},
         Image, ImageAspects, ImageCreateInfo, ImageLayout, ImageSubresourceLayers, ImageType,
         ImageUsage, SampleCount,
     },
+    instance::debug::DebugUtilsLabel,
     memory::{
         allocator::{
             AllocationCreateInfo, DeviceLayout, MemoryTypeFilter, StandardMemoryAllocator,
@@ -50,8 +56,8 @@ use vulkano::{
             },
             input_assembly::InputAssemblyState,
             multisample::MultisampleState,
-            rasterization::RasterizationState,
-            vertex_input::{Vertex, VertexDefinition},
+            rasterization::{PolygonMode, RasterizationState},
+            vertex_input::{Vertex, VertexDefinition, VertexInputState},
             viewport::{Scissor, Viewport, ViewportState},
             GraphicsPipelineCreateInfo,
         },
@@ -60,11 +66,30 @@ use vulkano::{
         PipelineShaderStageCreateInfo,
     },
     render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass, Subpass},
-    sync::GpuFuture,
-    DeviceSize, NonZeroDeviceSize,
+    shader::EntryPoint,
+    sync::{self, GpuFuture},
+    DeviceSize, NonZeroDeviceSize, Validated, VulkanError, VulkanObject,
 };
 
-use crate::utils::Allocators;
+#[cfg(feature = "dds")]
+use crate::compressed::record_texture_from_dds;
+#[cfg(feature = "ktx2")]
+use crate::compressed::record_texture_from_ktx2;
+#[cfg(feature = "image")]
+use crate::utils::{record_texture_from_dynamic, record_texture_from_file};
+use crate::{
+    diagnostics::{log_warn, warn_rate_limited},
+    inspect::{InspectError, InspectOptions, InspectedImage, SampleType},
+    profiling::profile_scope,
+    registrar::{PendingRegistration, TextureRegistrar},
+    timestamps::{GpuTimer, GpuTimings},
+    utils::{
+        immutable_texture_from_bytes, record_texture_from_bytes, Allocators, ImageCreationError,
+    },
+    video::{
+        self, VideoError, VideoPlane, VideoSlot, VideoTexture, YuvColorSpace, YuvFormat, YuvRange,
+    },
+};
 
 const VERTICES_PER_QUAD: DeviceSize = 4;
 const VERTEX_BUFFER_SIZE: DeviceSize = 1024 * 1024 * VERTICES_PER_QUAD;
@@ -72,6 +97,8 @@ const INDEX_BUFFER_SIZE: DeviceSize = 1024 * 1024 * 2;
 
 type VertexBuffer = Subbuffer<[egui::epaint::Vertex]>;
 type IndexBuffer = Subbuffer<[u32]>;
+// A YCbCr conversion and a pipeline with it as immutable sampler
+type YcbcrPipeline = (Arc<SamplerYcbcrConversion>, Arc<GraphicsPipeline>);
 
 /// Should match vertex definition of egui
 #[repr(C)]
@@ -85,13 +112,327 @@ pub struct EguiVertex {
     pub color: [u8; 4],
 }
 
+/// Debug visualizations drawn by the renderer, see `GuiConfig::debug_mode`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RenderDebugMode {
+    /// Regular rendering
+    #[default]
+    Off,
+    /// Outlines every scissor rectangle set for meshes and paint callbacks, each in its own color
+    ScissorRects,
+    /// Tints each draw call in its own color
+    Batches,
+    /// Draws the edges of all triangles over the GUI. Requires the `fill_mode_non_solid` device
+    /// feature, falls back to `Off` without it.
+    Wireframe,
+    /// Replaces the GUI with a heatmap of how many triangles cover each pixel, from dark red
+    /// (once) to white (often). With its own render pass, the area the GUI is drawn to is cleared
+    /// to black first. In a subpass, the heatmap is drawn over the app's scene instead.
+    Overdraw,
+}
+
+/// What to do when a mesh uses a texture that doesn't exist (anymore), see
+/// `GuiConfig::missing_texture_policy`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MissingTexturePolicy {
+    /// Leave the mesh out and log a (rate limited) warning
+    #[default]
+    Skip,
+    /// Draw the mesh with a magenta texture and log a (rate limited) warning
+    Placeholder,
+    /// Fail the `try_` draw functions with `DrawError::MissingTexture`. The other draw functions
+    /// skip the mesh like `Skip`.
+    Error,
+}
+
+/// What to do with paint callbacks that aren't a `CallbackFn`, e.g. ones meant for another egui
+/// backend. See `GuiConfig::unsupported_callback_policy`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UnsupportedCallbackPolicy {
+    /// Leave the callback out and log a (rate limited) warning
+    #[default]
+    Skip,
+    /// Fail the `try_` draw functions with `DrawError::UnsupportedCallback`. The other draw
+    /// functions skip the callback like `Skip`.
+    Error,
+}
+
+/// Options of a renderer that aren't tied to its device, copied as a whole by
+/// `Renderer::recreate_on_device`. Built from `GuiConfig` by `Gui::new`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RendererSettings {
+    /// See `GuiConfig::retain_texture_data`
+    pub retain_texture_data: bool,
+    /// See `GuiConfig::gpu_timestamps`
+    pub gpu_timestamps: bool,
+    /// See `GuiConfig::debug_mode`
+    pub debug_mode: RenderDebugMode,
+    /// See `GuiConfig::missing_texture_policy`
+    pub missing_texture_policy: MissingTexturePolicy,
+    /// See `GuiConfig::unsupported_callback_policy`
+    pub unsupported_callback_policy: UnsupportedCallbackPolicy,
+    /// See `GuiConfig::frames_in_flight`
+    pub frames_in_flight: u32,
+}
+
+impl Default for RendererSettings {
+    fn default() -> Self {
+        RendererSettings {
+            retain_texture_data: false,
+            gpu_timestamps: false,
+            debug_mode: RenderDebugMode::default(),
+            missing_texture_policy: MissingTexturePolicy::default(),
+            unsupported_callback_policy: UnsupportedCallbackPolicy::default(),
+            frames_in_flight: 2,
+        }
+    }
+}
+
+/// Why drawing the GUI failed, see `Gui::try_draw_on_image`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DrawError {
+    /// A mesh used a texture that doesn't exist, with `MissingTexturePolicy::Error`
+    MissingTexture(egui::TextureId),
+    /// A paint callback wasn't a `CallbackFn`, with `UnsupportedCallbackPolicy::Error`
+    UnsupportedCallback,
+}
+
+impl fmt::Display for DrawError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DrawError::MissingTexture(id) => write!(f, "texture {:?} doesn't exist", id),
+            DrawError::UnsupportedCallback => {
+                write!(f, "unsupported paint callback, expected egui_winit_vulkano::CallbackFn")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DrawError {}
+
+/// Why a user texture couldn't be replaced or updated, see `Gui::update_user_image_region`
+#[derive(Clone, Debug)]
+pub enum TextureUpdateError {
+    /// No user texture is registered under this id
+    UnknownTexture(egui::TextureId),
+    /// The texture's image wasn't created with `ImageUsage::TRANSFER_DST`
+    NotWritable,
+    /// Only single-plane color formats can be updated, not depth, stencil or multi-planar ones
+    UnsupportedFormat(Format),
+    /// The region doesn't fit in the image
+    RegionOutOfBounds,
+    /// The region doesn't cover whole blocks of the compressed format. Its offset must be a
+    /// multiple of the block extent, as must its extent unless it reaches the image's edge.
+    UnalignedRegion,
+    /// The data isn't the size of the tightly packed region
+    DataSize { expected: usize, actual: usize },
+    /// Allocating the staging buffer failed
+    Staging(Validated<AllocateBufferError>),
+    /// Submitting the upload failed
+    Upload(Validated<VulkanError>),
+}
+
+impl fmt::Display for TextureUpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextureUpdateError::UnknownTexture(id) => write!(f, "texture {:?} doesn't exist", id),
+            TextureUpdateError::NotWritable => {
+                write!(f, "texture image wasn't created with TRANSFER_DST usage")
+            }
+            TextureUpdateError::UnsupportedFormat(format) => {
+                write!(f, "format {:?} isn't a single-plane color format", format)
+            }
+            TextureUpdateError::RegionOutOfBounds => write!(f, "region is out of the image"),
+            TextureUpdateError::UnalignedRegion => {
+                write!(f, "region isn't aligned to the format's blocks")
+            }
+            TextureUpdateError::DataSize { expected, actual } => {
+                write!(f, "expected {} bytes of data, got {}", expected, actual)
+            }
+            TextureUpdateError::Staging(err) => {
+                write!(f, "failed to allocate the staging buffer: {}", err)
+            }
+            TextureUpdateError::Upload(err) => write!(f, "failed to upload the region: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for TextureUpdateError {}
+
+/// Pipelines of the debug visualizations, created once a `RenderDebugMode` is first enabled
+struct DebugPipelines {
+    batches: Arc<GraphicsPipeline>,
+    // None if the device lacks `fill_mode_non_solid`
+    wireframe: Option<Arc<GraphicsPipeline>>,
+    overdraw: Arc<GraphicsPipeline>,
+    scissor_rects: Arc<GraphicsPipeline>,
+}
+
+/// A mesh draw call, repeated by the debug visualizations
+struct DebugDraw {
+    scissor: Scissor,
+    index_count: u32,
+    first_index: u32,
+    vertex_offset: i32,
+}
+
+/// What the GUI cost in a single frame, see `Gui::last_frame_stats`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RenderStats {
+    /// Vertices drawn
+    pub vertices: usize,
+    /// Indices drawn
+    pub indices: usize,
+    pub draw_calls: usize,
+    pub descriptor_set_binds: usize,
+    pub scissor_changes: usize,
+    /// Paint callbacks invoked
+    pub paint_callbacks: usize,
+    /// Bytes of texture data uploaded for egui's texture updates
+    pub texture_upload_bytes: u64,
+    /// Memory held by all textures (managed and user) after the frame
+    pub texture_memory_bytes: u64,
+    /// Whether the previous frame's commands were reused as nothing changed. The draw counts
+    /// are those of the reused commands.
+    pub reused_commands: bool,
+}
+
+/// Commands of the last frame, reused while the frame stays the same
+struct CachedFrame {
+    hash: u64,
+    // What the commands were recorded from, compared on a hash match so that a collision is
+    // unlikely to draw a stale frame
+    textures_generation: u64,
+    scale_factor: f32,
+    framebuffer_dimensions: [u32; 2],
+    // Non-strict frames skip meshes that a strict frame fails on
+    strict: bool,
+    // Cheap to compare, unlike the meshes themselves
+    mesh_count: usize,
+    index_count: usize,
+    command_buffer: Arc<SecondaryAutoCommandBuffer>,
+    stats: RenderStats,
+}
+
+impl CachedFrame {
+    fn matches(
+        &self,
+        textures_generation: u64,
+        scale_factor: f32,
+        clipped_meshes: &[ClippedPrimitive],
+        framebuffer_dimensions: [u32; 2],
+        strict: bool,
+    ) -> bool {
+        self.textures_generation == textures_generation
+            && self.scale_factor == scale_factor
+            && self.framebuffer_dimensions == framebuffer_dimensions
+            && self.strict == strict
+            && self.mesh_count == clipped_meshes.len()
+            && self.index_count == index_count(clipped_meshes)
+    }
+}
+
+/// Indices of all meshes of a frame
+fn index_count(clipped_meshes: &[ClippedPrimitive]) -> usize {
+    clipped_meshes
+        .iter()
+        .map(|clipped| match &clipped.primitive {
+            Primitive::Mesh(mesh) => mesh.indices.len(),
+            Primitive::Callback(_) => 0,
+        })
+        .sum()
+}
+
+/// Source of a user image, kept as a CPU copy so that it can be re-uploaded on a new device.
+/// Borrows the caller's data until it is actually retained.
+struct RetainedUserImage<'a> {
+    source: RetainedImageSource<'a>,
+    format: Format,
+    sampler_create_info: SamplerCreateInfo,
+}
+
+enum RetainedImageSource<'a> {
+    /// Tightly packed pixels as given to `register_image_from_bytes`
+    Bytes { data: Cow<'a, [u8]>, dimensions: [u32; 2] },
+    /// Encoded image file as given to `register_image_from_file`
+    #[cfg(feature = "image")]
+    File(Cow<'a, [u8]>),
+    /// Decoded image as given to `register_image_from_dynamic`
+    #[cfg(feature = "image")]
+    Dynamic(Cow<'a, image::DynamicImage>),
+    /// KTX2 file as given to `register_image_from_ktx2`
+    #[cfg(feature = "ktx2")]
+    Ktx2(Cow<'a, [u8]>),
+    /// DDS file as given to `register_image_from_dds`
+    #[cfg(feature = "dds")]
+    Dds(Cow<'a, [u8]>),
+}
+
+impl RetainedUserImage<'_> {
+    fn into_owned(self) -> RetainedUserImage<'static> {
+        let source = match self.source {
+            RetainedImageSource::Bytes { data, dimensions } => {
+                RetainedImageSource::Bytes { data: Cow::Owned(data.into_owned()), dimensions }
+            }
+            #[cfg(feature = "image")]
+            RetainedImageSource::File(file_bytes) => {
+                RetainedImageSource::File(Cow::Owned(file_bytes.into_owned()))
+            }
+            #[cfg(feature = "image")]
+            RetainedImageSource::Dynamic(image) => {
+                RetainedImageSource::Dynamic(Cow::Owned(image.into_owned()))
+            }
+            #[cfg(feature = "ktx2")]
+            RetainedImageSource::Ktx2(file_bytes) => {
+                RetainedImageSource::Ktx2(Cow::Owned(file_bytes.into_owned()))
+            }
+            #[cfg(feature = "dds")]
+            RetainedImageSource::Dds(file_bytes) => {
+                RetainedImageSource::Dds(Cow::Owned(file_bytes.into_owned()))
+            }
+        };
+        RetainedUserImage {
+            source,
+            format: self.format,
+            sampler_create_info: self.sampler_create_info,
+        }
+    }
+
+    fn record_upload(
+        &self,
+        allocators: &Allocators,
+        cbb: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Result<Arc<ImageView>, ImageCreationError> {
+        match &self.source {
+            RetainedImageSource::Bytes { data, dimensions } => {
+                record_texture_from_bytes(allocators, cbb, data, *dimensions, self.format)
+            }
+            #[cfg(feature = "image")]
+            RetainedImageSource::File(file_bytes) => {
+                record_texture_from_file(allocators, cbb, file_bytes, self.format)
+            }
+            #[cfg(feature = "image")]
+            RetainedImageSource::Dynamic(image) => {
+                record_texture_from_dynamic(allocators, cbb, image, self.format)
+            }
+            #[cfg(feature = "ktx2")]
+            RetainedImageSource::Ktx2(file_bytes) => {
+                record_texture_from_ktx2(allocators, cbb, file_bytes)
+            }
+            #[cfg(feature = "dds")]
+            RetainedImageSource::Dds(file_bytes) => {
+                record_texture_from_dds(allocators, cbb, file_bytes)
+            }
+        }
+    }
+}
+
 pub struct Renderer {
     gfx_queue: Arc<Queue>,
     render_pass: Option<Arc<RenderPass>>,
     is_overlay: bool,
     output_in_linear_colorspace: bool,
 
-    #[allow(unused)]
     format: vulkano::format::Format,
     font_sampler: Arc<Sampler>,
     // May be R8G8_UNORM or R8G8B8A8_SRGB
@@ -104,7 +445,39 @@ pub struct Renderer {
 
     texture_desc_sets: AHashMap<egui::TextureId, Arc<DescriptorSet>>,
     texture_images: AHashMap<egui::TextureId, Arc<ImageView>>,
-    next_native_tex_id: u64,
+    // Samplers of user textures, kept when their image is replaced
+    user_samplers: AHashMap<egui::TextureId, Arc<Sampler>>,
+    // Images drawn with an inspector shader variant instead of `pipeline`
+    inspected_images: AHashMap<egui::TextureId, InspectedImage>,
+    inspect_pipelines: AHashMap<(SampleType, bool), Arc<GraphicsPipeline>>,
+    video_textures: AHashMap<egui::TextureId, VideoTexture>,
+    // Converts separately uploaded YUV planes, for videos without a YCbCr conversion
+    yuv_planes_pipeline: Option<Arc<GraphicsPipeline>>,
+    // Shared by videos of the same kind, None if the device can't sample the format through a
+    // YCbCr conversion
+    ycbcr_pipelines: AHashMap<(YuvFormat, YuvColorSpace, YuvRange), Option<YcbcrPipeline>>,
+    // Allocates user texture ids and queues registrations from other threads
+    registrar: TextureRegistrar,
+    // User texture uploads submitted since the last frame, which waits for them
+    upload_future: Option<Box<dyn GpuFuture>>,
+
+    settings: RendererSettings,
+    // CPU copies of textures, only filled when `settings.retain_texture_data` is set.
+    // Full images of managed textures with their deltas applied.
+    retained_managed_textures: AHashMap<egui::TextureId, egui::epaint::ImageDelta>,
+    retained_user_images: AHashMap<egui::TextureId, RetainedUserImage<'static>>,
+
+    // Bumped whenever a texture descriptor set is added or removed, invalidates `cached_frame`.
+    textures_generation: u64,
+    cached_frame: Option<CachedFrame>,
+    last_frame_changed: bool,
+    stats: RenderStats,
+    gpu_timer: Option<GpuTimer>,
+    // Whether the instance has `ext_debug_utils` enabled, for object names and labels
+    debug_utils: bool,
+    debug_pipelines: Option<DebugPipelines>,
+    // Magenta texture drawn for missing textures, created with `MissingTexturePolicy::Placeholder`
+    placeholder_desc_set: Option<Arc<DescriptorSet>>,
 }
 
 impl Renderer {
@@ -196,7 +569,8 @@ impl Renderer {
         )
         .unwrap();
         let font_format = Self::choose_font_format(gfx_queue.device());
-        Renderer {
+        let debug_utils = gfx_queue.device().instance().enabled_extensions().ext_debug_utils;
+        let renderer = Renderer {
             gfx_queue,
             format: final_output_format,
             render_pass,
@@ -205,25 +579,264 @@ impl Renderer {
             subpass,
             texture_desc_sets: AHashMap::default(),
             texture_images: AHashMap::default(),
-            next_native_tex_id: 0,
+            user_samplers: AHashMap::default(),
+            inspected_images: AHashMap::default(),
+            inspect_pipelines: AHashMap::default(),
+            video_textures: AHashMap::default(),
+            yuv_planes_pipeline: None,
+            ycbcr_pipelines: AHashMap::default(),
+            registrar: TextureRegistrar::new(),
+            upload_future: None,
+            settings: RendererSettings::default(),
+            retained_managed_textures: AHashMap::default(),
+            retained_user_images: AHashMap::default(),
+            textures_generation: 0,
+            cached_frame: None,
+            last_frame_changed: true,
+            stats: RenderStats::default(),
+            gpu_timer: None,
+            debug_utils,
+            debug_pipelines: None,
+            placeholder_desc_set: None,
             is_overlay,
             output_in_linear_colorspace,
             font_sampler,
             font_format,
             allocators,
-        }
+        };
+        renderer.set_debug_name(renderer.pipeline.as_ref(), "egui pipeline");
+        renderer.set_debug_name(renderer.font_sampler.as_ref(), "egui font sampler");
+        renderer
     }
 
     pub fn has_renderpass(&self) -> bool {
         self.render_pass.is_some()
     }
 
+    /// Whether to keep CPU copies of managed textures and of user images registered from bytes
+    pub fn set_retain_texture_data(&mut self, retain: bool) {
+        self.settings.retain_texture_data = retain;
+        if !retain {
+            self.retained_managed_textures.clear();
+            self.retained_user_images.clear();
+        }
+    }
+
+    /// Whether the last drawn frame differed from the one before it, either in its draw commands
+    /// or in its texture contents
+    pub fn last_frame_changed(&self) -> bool {
+        self.last_frame_changed
+    }
+
+    /// Stats of the last drawn frame
+    pub fn last_frame_stats(&self) -> RenderStats {
+        self.stats
+    }
+
+    /// Enables writing GPU timestamps around the GUI pass and each paint callback. Stays disabled
+    /// if the queue doesn't support timestamps. Frames are always re-recorded while enabled.
+    pub fn set_gpu_timestamps(&mut self, enabled: bool) {
+        self.settings.gpu_timestamps = enabled;
+        self.gpu_timer = if enabled {
+            GpuTimer::new(&self.gfx_queue, self.settings.frames_in_flight)
+        } else {
+            None
+        };
+    }
+
+    /// Latest resolved GPU timings, a few frames old. None if timestamps are disabled or no
+    /// results are available yet.
+    pub fn gpu_timings(&self) -> Option<&GpuTimings> {
+        self.gpu_timer.as_ref().and_then(GpuTimer::latest)
+    }
+
+    /// Switches the debug visualization, creating its pipelines on first use
+    pub fn set_debug_mode(&mut self, mode: RenderDebugMode) {
+        if mode != RenderDebugMode::Off && self.debug_pipelines.is_none() {
+            self.debug_pipelines =
+                Some(Self::create_debug_pipelines(&self.gfx_queue, &self.subpass));
+        }
+        let wireframe_supported =
+            self.debug_pipelines.as_ref().is_some_and(|pipelines| pipelines.wireframe.is_some());
+        self.settings.debug_mode = if mode == RenderDebugMode::Wireframe && !wireframe_supported {
+            log_warn!("Wireframe debug mode requires the fill_mode_non_solid device feature");
+            RenderDebugMode::Off
+        } else {
+            mode
+        };
+        // Recorded commands include the previous visualization
+        self.cached_frame = None;
+    }
+
+    pub fn debug_mode(&self) -> RenderDebugMode {
+        self.settings.debug_mode
+    }
+
+    pub fn set_missing_texture_policy(&mut self, policy: MissingTexturePolicy) {
+        if policy == MissingTexturePolicy::Placeholder && self.placeholder_desc_set.is_none() {
+            let view = immutable_texture_from_bytes(
+                &self.allocators,
+                self.gfx_queue.clone(),
+                &[255, 0, 255, 255],
+                [1, 1],
+                Format::R8G8B8A8_SRGB,
+            )
+            .expect("Failed to create placeholder texture");
+            self.set_debug_name(view.image().as_ref(), "egui missing texture placeholder");
+            let layout = self.pipeline.layout().set_layouts().first().unwrap();
+            self.placeholder_desc_set =
+                Some(self.sampled_image_desc_set(layout, view, self.font_sampler.clone()));
+        }
+        self.settings.missing_texture_policy = policy;
+        // Recorded commands may have left out meshes under the previous policy
+        self.cached_frame = None;
+    }
+
+    pub fn set_unsupported_callback_policy(&mut self, policy: UnsupportedCallbackPolicy) {
+        self.settings.unsupported_callback_policy = policy;
+    }
+
+    /// Sizes the image rings of videos registered afterwards, and bounds how long GPU timestamps
+    /// wait for their results
+    pub fn set_frames_in_flight(&mut self, frames_in_flight: u32) {
+        self.settings.frames_in_flight = frames_in_flight;
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.set_frames_in_flight(frames_in_flight);
+        }
+    }
+
+    pub fn retains_texture_data(&self) -> bool {
+        self.settings.retain_texture_data
+    }
+
+    /// Applies all settings at once, e.g. from `GuiConfig` or from another renderer
+    pub fn apply_settings(&mut self, settings: RendererSettings) {
+        self.set_retain_texture_data(settings.retain_texture_data);
+        self.set_gpu_timestamps(settings.gpu_timestamps);
+        self.set_debug_mode(settings.debug_mode);
+        self.set_missing_texture_policy(settings.missing_texture_policy);
+        self.set_unsupported_callback_policy(settings.unsupported_callback_policy);
+        self.set_frames_in_flight(settings.frames_in_flight);
+    }
+
+    /// Rebuilds all GPU state on the device of `gfx_queue` and re-uploads the retained textures.
+    /// Pass `subpass` if this renderer was created with a subpass, `None` to recreate its own
+    /// render pass. Returns the user textures that were dropped: ones without a CPU copy, which
+    /// includes all videos, and ones that failed to upload on the new device.
+    pub fn recreate_on_device(
+        &mut self,
+        gfx_queue: Arc<Queue>,
+        subpass: Option<Subpass>,
+    ) -> Vec<egui::TextureId> {
+        let mut renderer = match subpass {
+            Some(subpass) => Self::new_with_subpass(gfx_queue, self.format, subpass),
+            None => Self::new_with_render_pass(
+                gfx_queue,
+                self.format,
+                self.is_overlay,
+                self.subpass.num_samples().unwrap_or(SampleCount::Sample1),
+            ),
+        };
+        renderer.apply_settings(self.settings);
+        renderer.registrar = self.registrar.clone();
+        let old = std::mem::replace(self, renderer);
+
+        // Videos are lost too, including ones without a frame (and thus an image) yet
+        let mut lost: Vec<_> = old
+            .texture_images
+            .keys()
+            .filter(|id| {
+                !old.retained_managed_textures.contains_key(id)
+                    && !old.retained_user_images.contains_key(id)
+                    && !old.video_textures.contains_key(id)
+            })
+            .chain(old.video_textures.keys())
+            .copied()
+            .collect();
+
+        let managed = old.retained_managed_textures.into_iter().collect::<Vec<_>>();
+        self.update_textures(&managed);
+        for (id, retained) in old.retained_user_images {
+            match self.upload_retained_image(&retained) {
+                Ok(image) => {
+                    self.insert_image(id, image, retained.sampler_create_info.clone());
+                    self.retained_user_images.insert(id, retained);
+                }
+                // E.g. a compressed format the new device can't sample nor we decode
+                Err(err) => {
+                    log_warn!("Failed to recreate texture {:?}: {:?}", id, err);
+                    lost.push(id);
+                }
+            }
+        }
+        lost
+    }
+
+    /// Names a Vulkan object for debuggers such as RenderDoc, if `ext_debug_utils` is enabled
+    fn set_debug_name<T: VulkanObject + DeviceOwned>(&self, object: &T, name: &str) {
+        if self.debug_utils {
+            // Names are only a debugging aid, not worth failing over
+            let _ = self.gfx_queue.device().set_debug_utils_object_name(object, Some(name));
+        }
+    }
+
+    /// Opens a labeled region of commands, if `ext_debug_utils` is enabled
+    fn begin_debug_label<L>(&self, builder: &mut AutoCommandBufferBuilder<L>, name: &str) {
+        if self.debug_utils {
+            builder
+                .begin_debug_utils_label(DebugUtilsLabel {
+                    label_name: name.to_owned(),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+    }
+
+    /// Closes the region opened by `begin_debug_label`
+    fn end_debug_label<L>(&self, builder: &mut AutoCommandBufferBuilder<L>) {
+        if self.debug_utils {
+            // Safe: the region was opened by `begin_debug_label` in this same command buffer.
+            unsafe { builder.end_debug_utils_label() }.unwrap();
+        }
+    }
+
     fn create_pipeline(gfx_queue: Arc<Queue>, subpass: Subpass) -> Arc<GraphicsPipeline> {
-        let vs = vs::load(gfx_queue.device().clone())
+        let fs = fs::load(gfx_queue.device().clone())
             .expect("failed to create shader module")
             .entry_point("main")
             .unwrap();
-        let fs = fs::load(gfx_queue.device().clone())
+        Self::create_pipeline_with_fs(gfx_queue, subpass, fs, None)
+    }
+
+    /// The inspector shader variant for the sample type and dimensionality of an image
+    fn create_inspect_pipeline(
+        gfx_queue: Arc<Queue>,
+        subpass: Subpass,
+        sample_type: SampleType,
+        is_3d: bool,
+    ) -> Arc<GraphicsPipeline> {
+        let device = gfx_queue.device().clone();
+        let module = match (sample_type, is_3d) {
+            (SampleType::Float, false) => inspect_float_fs::load(device),
+            (SampleType::Uint, false) => inspect_uint_fs::load(device),
+            (SampleType::Sint, false) => inspect_sint_fs::load(device),
+            (SampleType::Float, true) => inspect_float_3d_fs::load(device),
+            (SampleType::Uint, true) => inspect_uint_3d_fs::load(device),
+            (SampleType::Sint, true) => inspect_sint_3d_fs::load(device),
+        };
+        let fs = module.expect("failed to create shader module").entry_point("main").unwrap();
+        Self::create_pipeline_with_fs(gfx_queue, subpass, fs, None)
+    }
+
+    /// The GUI pipeline, drawing egui's meshes with the given fragment shader. Samplers with a
+    /// YCbCr conversion must be part of the layout, as `immutable_sampler` of binding 0.
+    fn create_pipeline_with_fs(
+        gfx_queue: Arc<Queue>,
+        subpass: Subpass,
+        fs: EntryPoint,
+        immutable_sampler: Option<Arc<Sampler>>,
+    ) -> Arc<GraphicsPipeline> {
+        let vs = vs::load(gfx_queue.device().clone())
             .expect("failed to create shader module")
             .entry_point("main")
             .unwrap();
@@ -245,9 +858,14 @@ impl Renderer {
         let stages =
             [PipelineShaderStageCreateInfo::new(vs), PipelineShaderStageCreateInfo::new(fs)];
 
+        let mut layout_create_info = PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages);
+        if let Some(sampler) = immutable_sampler {
+            layout_create_info.set_layouts[0].bindings.get_mut(&0).unwrap().immutable_samplers =
+                vec![sampler];
+        }
         let layout = PipelineLayout::new(
             gfx_queue.device().clone(),
-            PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+            layout_create_info
                 .into_pipeline_layout_create_info(gfx_queue.device().clone())
                 .unwrap(),
         )
@@ -277,6 +895,118 @@ impl Renderer {
         .unwrap()
     }
 
+    fn create_debug_pipelines(gfx_queue: &Arc<Queue>, subpass: &Subpass) -> DebugPipelines {
+        let device = gfx_queue.device();
+        let vs = debug_vs::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        let fs = debug_fs::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        let rect_vs = debug_rect_vs::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        let rect_fs = debug_rect_fs::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        let mesh_input = EguiVertex::per_vertex().definition(&vs).unwrap();
+
+        let create = |vs, fs, vertex_input_state, polygon_mode, blend| {
+            Self::create_debug_pipeline(
+                gfx_queue,
+                subpass,
+                vs,
+                fs,
+                vertex_input_state,
+                polygon_mode,
+                blend,
+            )
+        };
+        DebugPipelines {
+            batches: create(
+                vs.clone(),
+                fs.clone(),
+                mesh_input.clone(),
+                PolygonMode::Fill,
+                AttachmentBlend::alpha(),
+            ),
+            wireframe: device.enabled_features().fill_mode_non_solid.then(|| {
+                create(
+                    vs.clone(),
+                    fs.clone(),
+                    mesh_input.clone(),
+                    PolygonMode::Line,
+                    AttachmentBlend::alpha(),
+                )
+            }),
+            overdraw: create(vs, fs, mesh_input, PolygonMode::Fill, AttachmentBlend::additive()),
+            scissor_rects: create(
+                rect_vs,
+                rect_fs,
+                VertexInputState::new(),
+                PolygonMode::Fill,
+                AttachmentBlend::alpha(),
+            ),
+        }
+    }
+
+    fn create_debug_pipeline(
+        gfx_queue: &Arc<Queue>,
+        subpass: &Subpass,
+        vs: EntryPoint,
+        fs: EntryPoint,
+        vertex_input_state: VertexInputState,
+        polygon_mode: PolygonMode,
+        blend: AttachmentBlend,
+    ) -> Arc<GraphicsPipeline> {
+        let stages =
+            [PipelineShaderStageCreateInfo::new(vs), PipelineShaderStageCreateInfo::new(fs)];
+
+        let layout = PipelineLayout::new(
+            gfx_queue.device().clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                .into_pipeline_layout_create_info(gfx_queue.device().clone())
+                .unwrap(),
+        )
+        .unwrap();
+
+        GraphicsPipeline::new(
+            gfx_queue.device().clone(),
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(vertex_input_state),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState {
+                    polygon_mode,
+                    ..Default::default()
+                }),
+                multisample_state: Some(MultisampleState {
+                    rasterization_samples: subpass.num_samples().unwrap_or(SampleCount::Sample1),
+                    ..Default::default()
+                }),
+                color_blend_state: Some(ColorBlendState {
+                    attachments: vec![ColorBlendAttachmentState {
+                        blend: Some(blend),
+                        ..Default::default()
+                    }],
+                    ..ColorBlendState::default()
+                }),
+                dynamic_state: [DynamicState::Viewport, DynamicState::Scissor]
+                    .into_iter()
+                    .collect(),
+                subpass: Some(subpass.clone().into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )
+        .unwrap()
+    }
+
     /// Creates a descriptor set for images
     fn sampled_image_desc_set(
         &self,
@@ -299,20 +1029,766 @@ impl Renderer {
         image: Arc<ImageView>,
         sampler_create_info: SamplerCreateInfo,
     ) -> egui::TextureId {
-        let layout = self.pipeline.layout().set_layouts().first().unwrap();
+        let id = self.registrar.allocate_id();
+        self.insert_image(id, image, sampler_create_info);
+        id
+    }
+
+    /// Registers a user texture from tightly packed pixel bytes.
+    /// A CPU copy of the bytes is kept if texture data is retained.
+    pub fn register_image_from_bytes(
+        &mut self,
+        image_byte_data: &[u8],
+        dimensions: [u32; 2],
+        format: Format,
+        sampler_create_info: SamplerCreateInfo,
+    ) -> egui::TextureId {
+        let retained = RetainedUserImage {
+            source: RetainedImageSource::Bytes { data: Cow::Borrowed(image_byte_data), dimensions },
+            format,
+            sampler_create_info,
+        };
+        self.register_retained_image(retained)
+    }
+
+    /// Registers a user texture from an encoded image file.
+    /// A CPU copy of the file bytes is kept if texture data is retained.
+    #[cfg(feature = "image")]
+    pub fn register_image_from_file(
+        &mut self,
+        image_file_bytes: &[u8],
+        format: Format,
+        sampler_create_info: SamplerCreateInfo,
+    ) -> egui::TextureId {
+        let retained = RetainedUserImage {
+            source: RetainedImageSource::File(Cow::Borrowed(image_file_bytes)),
+            format,
+            sampler_create_info,
+        };
+        self.register_retained_image(retained)
+    }
+
+    #[cfg(feature = "image")]
+    pub fn register_image_from_dynamic(
+        &mut self,
+        image: &image::DynamicImage,
+        format: Format,
+        sampler_create_info: SamplerCreateInfo,
+    ) -> egui::TextureId {
+        let retained = RetainedUserImage {
+            source: RetainedImageSource::Dynamic(Cow::Borrowed(image)),
+            format,
+            sampler_create_info,
+        };
+        self.register_retained_image(retained)
+    }
+
+    /// Like `register_image_from_dynamic`, but keeps the image itself as the CPU copy
+    #[cfg(feature = "image")]
+    pub(crate) fn register_owned_image(
+        &mut self,
+        image: image::DynamicImage,
+        format: Format,
+        sampler_create_info: SamplerCreateInfo,
+    ) -> egui::TextureId {
+        let retained = RetainedUserImage {
+            source: RetainedImageSource::Dynamic(Cow::Owned(image)),
+            format,
+            sampler_create_info,
+        };
+        self.register_retained_image(retained)
+    }
+
+    /// Registers a user texture from a KTX2 file, keeping its compressed format if the device
+    /// supports it. A CPU copy of the file bytes is kept if texture data is retained.
+    #[cfg(feature = "ktx2")]
+    pub fn register_image_from_ktx2(
+        &mut self,
+        file_bytes: &[u8],
+        sampler_create_info: SamplerCreateInfo,
+    ) -> Result<egui::TextureId, ImageCreationError> {
+        let retained = RetainedUserImage {
+            source: RetainedImageSource::Ktx2(Cow::Borrowed(file_bytes)),
+            // Read from the file
+            format: Format::UNDEFINED,
+            sampler_create_info,
+        };
+        self.try_register_retained_image(retained)
+    }
+
+    /// Registers a user texture from a DDS file, keeping its compressed format if the device
+    /// supports it. A CPU copy of the file bytes is kept if texture data is retained.
+    #[cfg(feature = "dds")]
+    pub fn register_image_from_dds(
+        &mut self,
+        file_bytes: &[u8],
+        sampler_create_info: SamplerCreateInfo,
+    ) -> Result<egui::TextureId, ImageCreationError> {
+        let retained = RetainedUserImage {
+            source: RetainedImageSource::Dds(Cow::Borrowed(file_bytes)),
+            // Read from the file
+            format: Format::UNDEFINED,
+            sampler_create_info,
+        };
+        self.try_register_retained_image(retained)
+    }
+
+    /// Like `register_retained_image`, for sources that may fail to upload
+    #[cfg(any(feature = "ktx2", feature = "dds"))]
+    fn try_register_retained_image(
+        &mut self,
+        retained: RetainedUserImage<'_>,
+    ) -> Result<egui::TextureId, ImageCreationError> {
+        let image = self.upload_retained_image(&retained)?;
+        let id = self.register_image(image, retained.sampler_create_info.clone());
+        if self.settings.retain_texture_data {
+            self.retained_user_images.insert(id, retained.into_owned());
+        }
+        Ok(id)
+    }
+
+    /// Uploads a retained image under `id`, replacing its texture if it has one. Failures are
+    /// logged, as queued registrations have no caller to return them to.
+    fn insert_retained_image(
+        &mut self,
+        id: egui::TextureId,
+        retained: RetainedUserImage<'_>,
+    ) -> Result<(), ImageCreationError> {
+        let image = self.upload_retained_image(&retained).inspect_err(|err| {
+            log_warn!("Failed to create queued texture {:?}: {:?}", id, err);
+        })?;
+        self.insert_image(id, image, retained.sampler_create_info.clone());
+        if self.settings.retain_texture_data {
+            self.retained_user_images.insert(id, retained.into_owned());
+        }
+        Ok(())
+    }
+
+    fn register_retained_image(&mut self, retained: RetainedUserImage<'_>) -> egui::TextureId {
+        let image = self.upload_retained_image(&retained).expect("Failed to create image");
+        let id = self.register_image(image, retained.sampler_create_info.clone());
+        if self.settings.retain_texture_data {
+            self.retained_user_images.insert(id, retained.into_owned());
+        }
+        id
+    }
+
+    fn upload_retained_image(
+        &mut self,
+        retained: &RetainedUserImage<'_>,
+    ) -> Result<Arc<ImageView>, ImageCreationError> {
+        self.submit_upload(|allocators, cbb| retained.record_upload(allocators, cbb))
+    }
+
+    /// Records an upload with `record` and submits it without waiting for it. The images are
+    /// ready by the next frame, as `draw_on_image` chains its commands after the uploads and
+    /// `draw_on_subpass_image` waits for them.
+    fn submit_upload(
+        &mut self,
+        record: impl FnOnce(
+            &Allocators,
+            &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        ) -> Result<Arc<ImageView>, ImageCreationError>,
+    ) -> Result<Arc<ImageView>, ImageCreationError> {
+        let mut cbb = AutoCommandBufferBuilder::primary(
+            self.allocators.command_buffer.clone(),
+            self.gfx_queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .map_err(ImageCreationError::Vulkan)?;
+        let image = record(&self.allocators, &mut cbb)?;
+        self.chain_upload(cbb.build().map_err(ImageCreationError::Vulkan)?)
+            .map_err(ImageCreationError::Vulkan)?;
+        Ok(image)
+    }
+
+    /// Submits a transfer command buffer after the uploads since the last frame
+    fn chain_upload(
+        &mut self,
+        command_buffer: Arc<PrimaryAutoCommandBuffer>,
+    ) -> Result<(), Validated<VulkanError>> {
+        let before = self
+            .upload_future
+            .take()
+            .unwrap_or_else(|| sync::now(self.gfx_queue.device().clone()).boxed());
+        let future = before.then_execute(self.gfx_queue.clone(), command_buffer).unwrap();
+        // Submitted right away, so the GPU doesn't wait for the frame to start on it
+        future.flush()?;
+        self.upload_future = Some(future.boxed());
+        Ok(())
+    }
+
+    /// Waits for the uploads submitted since the last frame, for command buffers that can't
+    /// chain after them
+    fn wait_for_uploads(&mut self) {
+        if let Some(future) = self.upload_future.take() {
+            future.then_signal_fence_and_flush().unwrap().wait(None).unwrap();
+        }
+    }
+
+    /// Registers a user texture under an id allocated before, replacing its texture if it has one
+    pub fn insert_image(
+        &mut self,
+        id: egui::TextureId,
+        image: Arc<ImageView>,
+        sampler_create_info: SamplerCreateInfo,
+    ) {
         let sampler = Sampler::new(self.gfx_queue.device().clone(), sampler_create_info).unwrap();
+        self.user_samplers.insert(id, sampler.clone());
+        self.insert_image_with_sampler(id, image, sampler);
+    }
+
+    fn insert_image_with_sampler(
+        &mut self,
+        id: egui::TextureId,
+        image: Arc<ImageView>,
+        sampler: Arc<Sampler>,
+    ) {
+        let layout = self.pipeline.layout().set_layouts().first().unwrap();
         let desc_set = self.sampled_image_desc_set(layout, image.clone(), sampler);
-        let id = egui::TextureId::User(self.next_native_tex_id);
-        self.next_native_tex_id += 1;
         self.texture_desc_sets.insert(id, desc_set);
         self.texture_images.insert(id, image);
-        id
+        self.textures_generation += 1;
+    }
+
+    /// Replaces the image of a user texture, keeping its id and sampler. Its CPU copy (if
+    /// retained) is dropped, like for images registered with `register_image`.
+    pub fn replace_image_view(
+        &mut self,
+        id: egui::TextureId,
+        image: Arc<ImageView>,
+    ) -> Result<(), TextureUpdateError> {
+        let sampler =
+            self.user_samplers.get(&id).cloned().ok_or(TextureUpdateError::UnknownTexture(id))?;
+        self.insert_image_with_sampler(id, image, sampler);
+        self.retained_user_images.remove(&id);
+        Ok(())
+    }
+
+    /// Overwrites a region of a user texture's image (its view's first mip level and layer) with
+    /// tightly packed texels of the image's format. The retained CPU copy is patched as well if
+    /// it holds plain bytes.
+    pub fn update_image_region(
+        &mut self,
+        id: egui::TextureId,
+        data: &[u8],
+        offset: [u32; 2],
+        extent: [u32; 2],
+    ) -> Result<(), TextureUpdateError> {
+        if !self.user_samplers.contains_key(&id) {
+            return Err(TextureUpdateError::UnknownTexture(id));
+        }
+        let view = self.texture_images[&id].clone();
+        if !view.image().usage().intersects(ImageUsage::TRANSFER_DST) {
+            return Err(TextureUpdateError::NotWritable);
+        }
+        let format = view.format();
+        // Multi-planar formats have plane aspects besides the color one
+        if format.aspects() != ImageAspects::COLOR {
+            return Err(TextureUpdateError::UnsupportedFormat(format));
+        }
+        let range = view.subresource_range();
+        let image_extent = view.image().extent();
+        let mip_extent = [0, 1].map(|axis| (image_extent[axis] >> range.mip_levels.start).max(1));
+        let end = [0, 1].map(|axis| offset[axis].checked_add(extent[axis]));
+        if (0..2).any(|axis| end[axis].map_or(true, |end| end > mip_extent[axis])) {
+            return Err(TextureUpdateError::RegionOutOfBounds);
+        }
+        let [block_width, block_height, _] = format.block_extent();
+        // Blocks cut off by the edge of the image are still copied whole
+        let aligned = |axis: usize, block: u32| {
+            offset[axis] % block == 0
+                && (extent[axis] % block == 0 || end[axis] == Some(mip_extent[axis]))
+        };
+        if !aligned(0, block_width) || !aligned(1, block_height) {
+            return Err(TextureUpdateError::UnalignedRegion);
+        }
+        let expected = extent[0].div_ceil(block_width) as usize
+            * extent[1].div_ceil(block_height) as usize
+            * format.block_size() as usize;
+        if data.len() != expected {
+            return Err(TextureUpdateError::DataSize { expected, actual: data.len() });
+        }
+        if extent.contains(&0) {
+            return Ok(());
+        }
+
+        let stage = Buffer::from_iter(
+            self.allocators.memory.clone(),
+            BufferCreateInfo { usage: BufferUsage::TRANSFER_SRC, ..Default::default() },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            data.iter().copied(),
+        )
+        .map_err(TextureUpdateError::Staging)?;
+        let mut cbb = AutoCommandBufferBuilder::primary(
+            self.allocators.command_buffer.clone(),
+            self.gfx_queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+        self.begin_debug_label(&mut cbb, "egui user texture update");
+        cbb.copy_buffer_to_image(CopyBufferToImageInfo {
+            regions: [BufferImageCopy {
+                image_offset: [offset[0], offset[1], 0],
+                image_extent: [extent[0], extent[1], 1],
+                image_subresource: ImageSubresourceLayers {
+                    aspects: ImageAspects::COLOR,
+                    mip_level: range.mip_levels.start,
+                    array_layers: range.array_layers.start..range.array_layers.start + 1,
+                },
+                ..Default::default()
+            }]
+            .into(),
+            ..CopyBufferToImageInfo::buffer_image(stage, view.image().clone())
+        })
+        .unwrap();
+        self.end_debug_label(&mut cbb);
+        // After pending uploads, which may include the image itself
+        self.chain_upload(cbb.build().unwrap()).map_err(TextureUpdateError::Upload)?;
+        self.stats.texture_upload_bytes += data.len() as u64;
+
+        if let Some(retained) = self.retained_user_images.get_mut(&id) {
+            match &mut retained.source {
+                RetainedImageSource::Bytes { data: into, dimensions }
+                    if retained.format.block_size() == format.block_size()
+                        && format.compression().is_none() =>
+                {
+                    let texel_size = format.block_size() as usize;
+                    let into_width = dimensions[0] as usize * texel_size;
+                    let from_width = extent[0] as usize * texel_size;
+                    let pos = [offset[0] as usize * texel_size, offset[1] as usize];
+                    blit_region(into.to_mut(), into_width, data, from_width, pos);
+                }
+                _ => {
+                    log_warn!(
+                        "Updated region of texture {:?} isn't retained, recreating on another \
+                         device restores the original image",
+                        id
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Registers an image to be shown by an inspector shader, which can show depth, stencil and
+    /// integer images, a single layer, mip level or 3D slice, isolated channels and remapped
+    /// values. Drawn like any user texture.
+    pub fn register_inspected_image(
+        &mut self,
+        image: Arc<Image>,
+        options: InspectOptions,
+        sampler_create_info: SamplerCreateInfo,
+    ) -> Result<egui::TextureId, InspectError> {
+        let inspected = InspectedImage::new(image, options, sampler_create_info)?;
+        let id = self.registrar.allocate_id();
+        self.insert_inspected_image(id, inspected);
+        Ok(id)
+    }
+
+    /// Changes how an image registered with `register_inspected_image` is shown
+    pub fn set_inspect_options(
+        &mut self,
+        id: egui::TextureId,
+        options: InspectOptions,
+    ) -> Result<(), InspectError> {
+        let current = self.inspected_images.get_mut(&id).ok_or(InspectError::UnknownTexture(id))?;
+        // Same view and sampler, keep the descriptor set
+        if current.update_in_place(options)? {
+            // Options only live in push constants, a cached frame would keep the old ones
+            self.textures_generation += 1;
+            return Ok(());
+        }
+        let inspected = InspectedImage::new(
+            current.image.clone(),
+            options,
+            current.sampler_create_info.clone(),
+        )?;
+        self.insert_inspected_image(id, inspected);
+        Ok(())
+    }
+
+    fn insert_inspected_image(&mut self, id: egui::TextureId, inspected: InspectedImage) {
+        let pipeline = self
+            .inspect_pipelines
+            .entry((inspected.sample_type, inspected.is_3d()))
+            .or_insert_with(|| {
+                Self::create_inspect_pipeline(
+                    self.gfx_queue.clone(),
+                    self.subpass.clone(),
+                    inspected.sample_type,
+                    inspected.is_3d(),
+                )
+            })
+            .clone();
+        let sampler =
+            Sampler::new(self.gfx_queue.device().clone(), inspected.sampler_create_info()).unwrap();
+        let layout = pipeline.layout().set_layouts().first().unwrap();
+        let desc_set = self.sampled_image_desc_set(layout, inspected.view.clone(), sampler);
+        self.texture_desc_sets.insert(id, desc_set);
+        self.texture_images.insert(id, inspected.view.clone());
+        self.inspected_images.insert(id, inspected);
+        // Options only live in push constants, a cached frame would keep the old ones
+        self.textures_generation += 1;
+    }
+
+    /// Registers a texture for streaming YUV 4:2:0 video frames with `update_video_frame`. Frames
+    /// are sampled through a YCbCr conversion if the device has `sampler_ycbcr_conversion`
+    /// enabled and supports the format, otherwise the planes are separate images converted by a
+    /// shader. Drawing it before the first frame is handled like a missing texture.
+    pub fn register_video(
+        &mut self,
+        format: YuvFormat,
+        extent: [u32; 2],
+        color_space: YuvColorSpace,
+        range: YuvRange,
+    ) -> Result<egui::TextureId, VideoError> {
+        if extent.iter().any(|&side| side == 0 || side % 2 != 0) {
+            return Err(VideoError::InvalidExtent(extent));
+        }
+        let id = self.registrar.allocate_id();
+        let device = self.gfx_queue.device().clone();
+        let ycbcr = self
+            .ycbcr_pipelines
+            .entry((format, color_space, range))
+            .or_insert_with(|| {
+                let conversion = video::ycbcr_conversion(&device, format, color_space, range)?;
+                let sampler = Sampler::new(
+                    device.clone(),
+                    SamplerCreateInfo {
+                        mag_filter: conversion.chroma_filter(),
+                        min_filter: conversion.chroma_filter(),
+                        address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                        sampler_ycbcr_conversion: Some(conversion.clone()),
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+                // Converted to RGB by the sampler, shown as is by the inspector shader
+                let fs = inspect_float_fs::load(device.clone())
+                    .expect("failed to create shader module")
+                    .entry_point("main")
+                    .unwrap();
+                let pipeline = Self::create_pipeline_with_fs(
+                    self.gfx_queue.clone(),
+                    self.subpass.clone(),
+                    fs,
+                    Some(sampler),
+                );
+                Some((conversion, pipeline))
+            })
+            .clone();
+        if ycbcr.is_none() && self.yuv_planes_pipeline.is_none() {
+            let fs = yuv_fs::load(device.clone())
+                .expect("failed to create shader module")
+                .entry_point("main")
+                .unwrap();
+            self.yuv_planes_pipeline = Some(Self::create_pipeline_with_fs(
+                self.gfx_queue.clone(),
+                self.subpass.clone(),
+                fs,
+                None,
+            ));
+        }
+        let create_image = |format: Format, extent: [u32; 2]| {
+            let image = Image::new(
+                self.allocators.memory.clone(),
+                ImageCreateInfo {
+                    image_type: ImageType::Dim2d,
+                    format,
+                    extent: [extent[0], extent[1], 1],
+                    usage: ImageUsage::TRANSFER_DST | ImageUsage::SAMPLED,
+                    ..Default::default()
+                },
+                AllocationCreateInfo::default(),
+            )
+            .unwrap();
+            self.set_debug_name(image.as_ref(), &format!("egui video {id:?}"));
+            image
+        };
+
+        let slot_count = self.settings.frames_in_flight as usize + 1;
+        let (ycbcr_pipeline, slots) = match ycbcr {
+            Some((conversion, pipeline)) => {
+                let layout = pipeline.layout().set_layouts().first().unwrap();
+                let slots = (0..slot_count)
+                    .map(|_| {
+                        let image = create_image(format.multi_planar_format(), extent);
+                        let view = ImageView::new(
+                            image.clone(),
+                            ImageViewCreateInfo {
+                                sampler_ycbcr_conversion: Some(conversion.clone()),
+                                ..ImageViewCreateInfo::from_image(&image)
+                            },
+                        )
+                        .unwrap();
+                        let desc_set = DescriptorSet::new(
+                            self.allocators.descriptor_set.clone(),
+                            layout.clone(),
+                            [WriteDescriptorSet::image_view(0, view.clone())],
+                            [],
+                        )
+                        .unwrap();
+                        VideoSlot { images: vec![image], view, desc_set, staging: None }
+                    })
+                    .collect();
+                (Some(pipeline), slots)
+            }
+            None => {
+                let pipeline = self.yuv_planes_pipeline.clone().unwrap();
+                let sampler = Sampler::new(
+                    device.clone(),
+                    SamplerCreateInfo {
+                        mag_filter: Filter::Linear,
+                        min_filter: Filter::Linear,
+                        address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+                let layout = pipeline.layout().set_layouts().first().unwrap();
+                let slots = (0..slot_count)
+                    .map(|_| {
+                        let images = (0..format.plane_count())
+                            .map(|plane| {
+                                create_image(
+                                    format.plane_format(plane),
+                                    format.plane_extent(extent, plane),
+                                )
+                            })
+                            .collect::<Vec<_>>();
+                        let views = images
+                            .iter()
+                            .map(|image| ImageView::new_default(image.clone()).unwrap())
+                            .collect::<Vec<_>>();
+                        // NV12 has no third plane, the shader doesn't read it then
+                        let writes = (0..3).map(|binding| {
+                            let view = views[binding.min(views.len() - 1)].clone();
+                            WriteDescriptorSet::image_view_sampler(
+                                binding as u32,
+                                view,
+                                sampler.clone(),
+                            )
+                        });
+                        let desc_set = DescriptorSet::new(
+                            self.allocators.descriptor_set.clone(),
+                            layout.clone(),
+                            writes,
+                            [],
+                        )
+                        .unwrap();
+                        VideoSlot { images, view: views[0].clone(), desc_set, staging: None }
+                    })
+                    .collect();
+                (None, slots)
+            }
+        };
+        self.video_textures.insert(
+            id,
+            VideoTexture {
+                format,
+                extent,
+                color_space,
+                range,
+                ycbcr_pipeline,
+                slots,
+                current_slot: None,
+            },
+        );
+        Ok(id)
+    }
+
+    /// Uploads the next frame of a video registered with `register_video`, as its Y and U & V (or
+    /// interleaved UV) planes. Frames go round a ring of `frames_in_flight + 1` sets of images
+    /// and staging buffers, so streaming allocates nothing per frame. The upload isn't waited
+    /// for, the next draw is.
+    pub fn update_video_frame(
+        &mut self,
+        id: egui::TextureId,
+        planes: &[VideoPlane<'_>],
+    ) -> Result<(), VideoError> {
+        let video = self.video_textures.get_mut(&id).ok_or(VideoError::UnknownTexture(id))?;
+        let offsets = video.plane_offsets(planes)?;
+        let size = *offsets.last().unwrap() as DeviceSize;
+        let slot = video.current_slot.map_or(0, |slot| (slot + 1) % video.slots.len());
+        let video_slot = &mut video.slots[slot];
+        if video_slot.staging.as_ref().map_or(true, |staging| staging.len() < size) {
+            let staging = Buffer::new_slice::<u8>(
+                self.allocators.memory.clone(),
+                BufferCreateInfo { usage: BufferUsage::TRANSFER_SRC, ..Default::default() },
+                AllocationCreateInfo {
+                    memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                        | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                    ..Default::default()
+                },
+                size,
+            )
+            .unwrap();
+            video_slot.staging = Some(staging);
+        }
+        let staging = video_slot.staging.clone().unwrap();
+        let video_slot = &video.slots[slot];
+        {
+            // Still locked if a pending frame uses the slot
+            let mut writer = staging.write().map_err(|_| VideoError::SlotInUse)?;
+            for (plane, range) in planes.iter().zip(offsets.windows(2)) {
+                let len = plane.data.len().min(range[1] - range[0]);
+                writer[range[0]..range[0] + len].copy_from_slice(&plane.data[..len]);
+            }
+        }
+
+        let mut cbb = AutoCommandBufferBuilder::primary(
+            self.allocators.command_buffer.clone(),
+            self.gfx_queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+        for (index, plane) in planes.iter().enumerate() {
+            let image = &video_slot.images[index.min(video_slot.images.len() - 1)];
+            let [width, height] = video.format.plane_extent(video.extent, index);
+            cbb.copy_buffer_to_image(CopyBufferToImageInfo {
+                regions: [BufferImageCopy {
+                    buffer_offset: offsets[index] as DeviceSize,
+                    buffer_row_length: (plane.stride / video.texel_size(index)) as u32,
+                    image_subresource: ImageSubresourceLayers {
+                        aspects: video.plane_aspect(index),
+                        mip_level: 0,
+                        array_layers: 0..1,
+                    },
+                    image_extent: [width, height, 1],
+                    ..Default::default()
+                }]
+                .into(),
+                ..CopyBufferToImageInfo::buffer_image(staging.clone(), image.clone())
+            })
+            .unwrap();
+        }
+        let (desc_set, view) = (video_slot.desc_set.clone(), video_slot.view.clone());
+        self.chain_upload(cbb.build().unwrap()).map_err(VideoError::Upload)?;
+
+        self.video_textures.get_mut(&id).unwrap().current_slot = Some(slot);
+        self.texture_desc_sets.insert(id, desc_set);
+        self.texture_images.insert(id, view);
+        self.textures_generation += 1;
+        self.stats.texture_upload_bytes += size;
+        Ok(())
+    }
+
+    /// Pipeline a texture is drawn with, `pipeline` unless it's inspected or a video
+    fn texture_pipeline(&self, id: egui::TextureId) -> &Arc<GraphicsPipeline> {
+        if let Some(inspected) = self.inspected_images.get(&id) {
+            return &self.inspect_pipelines[&(inspected.sample_type, inspected.is_3d())];
+        }
+        match self.video_textures.get(&id) {
+            Some(video) => {
+                video.ycbcr_pipeline.as_ref().or(self.yuv_planes_pipeline.as_ref()).unwrap()
+            }
+            None => &self.pipeline,
+        }
+    }
+
+    /// Pushes the per-texture constants of inspected images and videos
+    fn push_texture_constants(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<SecondaryAutoCommandBuffer>,
+        id: egui::TextureId,
+        push_constants: vs::PushConstants,
+    ) {
+        let layout = self.texture_pipeline(id).layout().clone();
+        let inspect_push_constants =
+            |channel, value_range, slice| inspect_float_fs::PushConstants {
+                screen_size: push_constants.screen_size,
+                output_in_linear_colorspace: push_constants.output_in_linear_colorspace,
+                channel,
+                value_range,
+                slice,
+            };
+        if let Some(inspected) = self.inspected_images.get(&id) {
+            let push_constants = inspect_push_constants(
+                inspected.channel_index(),
+                inspected.options.value_range,
+                inspected.slice_coordinate(),
+            );
+            builder.push_constants(layout, 0, push_constants).unwrap();
+        } else if let Some(video) = self.video_textures.get(&id) {
+            if video.ycbcr_pipeline.is_some() {
+                // Opaque RGB as converted by the sampler
+                builder.push_constants(layout, 0, inspect_push_constants(4, [0.0, 1.0], 0.0))
+            } else {
+                let push_constants = yuv_fs::PushConstants {
+                    screen_size: push_constants.screen_size,
+                    output_in_linear_colorspace: push_constants.output_in_linear_colorspace,
+                    two_plane: (video.format == YuvFormat::Nv12).into(),
+                    coefficients: video.color_space.coefficients(),
+                    full_range: (video.range == YuvRange::Full).into(),
+                };
+                builder.push_constants(layout, 0, push_constants)
+            }
+            .unwrap();
+        }
+    }
+
+    /// Handle for registering user textures from other threads
+    pub fn texture_registrar(&self) -> TextureRegistrar {
+        self.registrar.clone()
+    }
+
+    /// Applies the registrations queued by `TextureRegistrar` handles
+    fn apply_pending_registrations(&mut self) {
+        for registration in self.registrar.take_pending() {
+            match registration {
+                PendingRegistration::Bytes {
+                    id,
+                    data,
+                    dimensions,
+                    format,
+                    sampler_create_info,
+                } => {
+                    let retained = RetainedUserImage {
+                        source: RetainedImageSource::Bytes { data: Cow::Owned(data), dimensions },
+                        format,
+                        sampler_create_info,
+                    };
+                    let _ = self.insert_retained_image(id, retained);
+                }
+                #[cfg(feature = "image")]
+                PendingRegistration::Dynamic {
+                    id,
+                    image,
+                    format,
+                    sampler_create_info,
+                    on_uploaded,
+                } => {
+                    let retained = RetainedUserImage {
+                        source: RetainedImageSource::Dynamic(Cow::Owned(image)),
+                        format,
+                        sampler_create_info,
+                    };
+                    on_uploaded(self.insert_retained_image(id, retained));
+                }
+                PendingRegistration::View { id, image, sampler_create_info } => {
+                    self.insert_image(id, image, sampler_create_info);
+                }
+                PendingRegistration::Unregister(id) => self.unregister_image(id),
+            }
+        }
     }
 
     /// Unregister user texture.
     pub fn unregister_image(&mut self, texture_id: egui::TextureId) {
-        self.texture_desc_sets.remove(&texture_id);
+        if self.texture_desc_sets.remove(&texture_id).is_some() {
+            self.textures_generation += 1;
+        }
         self.texture_images.remove(&texture_id);
+        self.user_samplers.remove(&texture_id);
+        self.inspected_images.remove(&texture_id);
+        self.video_textures.remove(&texture_id);
+        self.retained_managed_textures.remove(&texture_id);
+        self.retained_user_images.remove(&texture_id);
     }
     /// Choose a font format, attempt to minimize memory footprint and CPU unpacking time
     /// by choosing a swizzled linear format.
@@ -458,6 +1934,7 @@ impl Renderer {
                 )
                 .unwrap()
             };
+            self.set_debug_name(img.as_ref(), &format!("egui texture {id:?}"));
             // Defer upload of data
             cbb.copy_buffer_to_image(CopyBufferToImageInfo::buffer_image(stage, img.clone()))
                 .unwrap();
@@ -483,13 +1960,41 @@ impl Renderer {
             // Save!
             self.texture_desc_sets.insert(id, desc_set);
             self.texture_images.insert(id, view);
+            self.textures_generation += 1;
         };
+
+        if self.settings.retain_texture_data {
+            self.retain_texture_delta(id, delta);
+        }
+    }
+    /// Apply a texture delta to the CPU copy of a managed texture.
+    fn retain_texture_delta(&mut self, id: egui::TextureId, delta: &egui::epaint::ImageDelta) {
+        let Some(pos) = delta.pos else {
+            self.retained_managed_textures.insert(id, delta.clone());
+            return;
+        };
+        let Some(retained) = self.retained_managed_textures.get_mut(&id) else {
+            // Texture was created before data was retained, nothing to patch.
+            return;
+        };
+        match (&mut retained.image, &delta.image) {
+            (egui::ImageData::Color(into), egui::ImageData::Color(from)) => {
+                let into = Arc::make_mut(into);
+                blit_region(&mut into.pixels, into.size[0], &from.pixels, from.size[0], pos);
+            }
+            (egui::ImageData::Font(into), egui::ImageData::Font(from)) => {
+                blit_region(&mut into.pixels, into.size[0], &from.pixels, from.size[0], pos);
+            }
+            _ => panic!("Mismatch between retained texture and delta image type"),
+        }
     }
     /// Write the entire texture delta for this frame.
     fn update_textures(&mut self, sets: &[(egui::TextureId, egui::epaint::ImageDelta)]) {
+        profile_scope!("Renderer::update_textures", textures = sets.len());
         // Allocate enough memory to upload every delta at once.
-        let total_size_bytes =
-            sets.iter().map(|(_, set)| self.image_size_bytes(set)).sum::<usize>() * 4;
+        let upload_bytes = sets.iter().map(|(_, set)| self.image_size_bytes(set)).sum::<usize>();
+        self.stats.texture_upload_bytes += upload_bytes as u64;
+        let total_size_bytes = upload_bytes * 4;
         // Infallible - unless we're on a 128 bit machine? :P
         let total_size_bytes = u64::try_from(total_size_bytes).unwrap();
         let Ok(total_size_bytes) = vulkano::NonZeroDeviceSize::try_from(total_size_bytes) else {
@@ -508,6 +2013,7 @@ impl Renderer {
             DeviceLayout::new(total_size_bytes, DeviceAlignment::MIN).unwrap(),
         )
         .unwrap();
+        self.set_debug_name(buffer.as_ref(), "egui texture staging buffer");
         let buffer = Subbuffer::new(buffer);
 
         // Shared command buffer for every upload in this batch.
@@ -517,6 +2023,7 @@ impl Renderer {
             CommandBufferUsage::OneTimeSubmit,
         )
         .unwrap();
+        self.begin_debug_label(&mut cbb, "egui texture upload");
 
         {
             // Scoped to keep writer lock bounded
@@ -540,6 +2047,7 @@ impl Renderer {
                 self.update_texture_within(*id, delta, stage, mapped_stage, &mut cbb);
             }
         }
+        self.end_debug_label(&mut cbb);
 
         // Execute every upload at once and await:
         let command_buffer = cbb.build().unwrap();
@@ -584,7 +2092,9 @@ impl Renderer {
         AutoCommandBufferBuilder::secondary(
             self.allocators.command_buffer.clone(),
             self.gfx_queue.queue_family_index(),
-            CommandBufferUsage::MultipleSubmit,
+            // Unchanged frames reuse the previous command buffer, which may still be pending from
+            // an earlier frame in flight.
+            CommandBufferUsage::SimultaneousUse,
             CommandBufferInheritanceInfo {
                 render_pass: Some(self.subpass.clone().into()),
                 ..Default::default()
@@ -618,6 +2128,10 @@ impl Renderer {
             CommandBufferUsage::OneTimeSubmit,
         )
         .unwrap();
+        // Queries can't be reset within the render pass
+        if let Some(timer) = &self.gpu_timer {
+            timer.reset(&mut command_buffer_builder);
+        }
         // Add clear values here for attachments and begin render pass
         command_buffer_builder
             .begin_render_pass(
@@ -642,65 +2156,219 @@ impl Renderer {
         scale_factor: f32,
         before_future: F,
         final_image: Arc<ImageView>,
-    ) -> Box<dyn GpuFuture>
+        strict: bool,
+    ) -> Result<Box<dyn GpuFuture>, DrawError>
     where
         F: GpuFuture + 'static,
     {
+        self.stats = RenderStats::default();
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.next_frame();
+        }
+        self.apply_pending_registrations();
         self.update_textures(&textures_delta.set);
 
         let (mut command_buffer_builder, framebuffer_dimensions) = self.start(final_image);
-        let mut builder = self.create_secondary_command_buffer_builder();
-        self.draw_egui(scale_factor, clipped_meshes, framebuffer_dimensions, &mut builder);
-        // Execute draw commands
-        let command_buffer = builder.build().unwrap();
-        command_buffer_builder.execute_commands(command_buffer).unwrap();
-        let done_future = self.finish(command_buffer_builder, Box::new(before_future));
+        let done_future = self
+            .record_egui(
+                scale_factor,
+                clipped_meshes,
+                textures_delta,
+                framebuffer_dimensions,
+                strict,
+            )
+            .map(|command_buffer| {
+                // Execute draw commands
+                command_buffer_builder.execute_commands(command_buffer).unwrap();
+                let before_future = match self.upload_future.take() {
+                    Some(uploads) => before_future.join(uploads).boxed(),
+                    None => before_future.boxed(),
+                };
+                self.finish(command_buffer_builder, before_future)
+            });
 
         for &id in &textures_delta.free {
             self.unregister_image(id);
         }
+        self.stats.texture_memory_bytes = self.texture_memory_bytes();
 
         done_future
     }
 
-    // Finishes the rendering pipeline
-    fn finish(
-        &self,
-        mut command_buffer_builder: AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
-        before_main_cb_future: Box<dyn GpuFuture>,
-    ) -> Box<dyn GpuFuture> {
-        // We end render pass
-        command_buffer_builder.end_render_pass(Default::default()).unwrap();
-        // Then execute our whole command buffer
-        let command_buffer = command_buffer_builder.build().unwrap();
-        let after_main_cb =
-            before_main_cb_future.then_execute(self.gfx_queue.clone(), command_buffer).unwrap();
-        // Return our future
-        Box::new(after_main_cb)
+    // Finishes the rendering pipeline
+    fn finish(
+        &self,
+        mut command_buffer_builder: AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        before_main_cb_future: Box<dyn GpuFuture>,
+    ) -> Box<dyn GpuFuture> {
+        // We end render pass
+        command_buffer_builder.end_render_pass(Default::default()).unwrap();
+        // Then execute our whole command buffer
+        let command_buffer = command_buffer_builder.build().unwrap();
+        let after_main_cb =
+            before_main_cb_future.then_execute(self.gfx_queue.clone(), command_buffer).unwrap();
+        // Return our future
+        Box::new(after_main_cb)
+    }
+
+    pub fn draw_on_subpass_image(
+        &mut self,
+        clipped_meshes: &[ClippedPrimitive],
+        textures_delta: &TexturesDelta,
+        scale_factor: f32,
+        framebuffer_dimensions: [u32; 2],
+        strict: bool,
+    ) -> Result<Arc<SecondaryAutoCommandBuffer>, DrawError> {
+        self.stats = RenderStats::default();
+        self.reset_gpu_timer_for_subpass();
+        self.apply_pending_registrations();
+        self.wait_for_uploads();
+        self.update_textures(&textures_delta.set);
+        let buffer = self.record_egui(
+            scale_factor,
+            clipped_meshes,
+            textures_delta,
+            framebuffer_dimensions,
+            strict,
+        );
+        for &id in &textures_delta.free {
+            self.unregister_image(id);
+        }
+        self.stats.texture_memory_bytes = self.texture_memory_bytes();
+        buffer
+    }
+
+    /// Moves on to the next frame's timestamp queries and resets them in a separate submission,
+    /// as the subpass' render pass is already begun when our commands execute. The reset is
+    /// flushed right away, so it is ordered before the caller's submission of our commands as
+    /// long as that goes to the same queue.
+    fn reset_gpu_timer_for_subpass(&mut self) {
+        let Some(timer) = &mut self.gpu_timer else {
+            return;
+        };
+        timer.next_frame();
+        if !timer.is_active() {
+            return;
+        }
+        let mut cbb = AutoCommandBufferBuilder::primary(
+            self.allocators.command_buffer.clone(),
+            self.gfx_queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+        timer.reset(&mut cbb);
+        let future = cbb
+            .build()
+            .unwrap()
+            .execute(self.gfx_queue.clone())
+            .unwrap()
+            .then_signal_fence_and_flush()
+            .unwrap();
+        timer.keep_reset_future(future.boxed());
+    }
+
+    /// Memory held by the images of all textures, each image counted once
+    fn texture_memory_bytes(&self) -> u64 {
+        let mut counted = ahash::AHashSet::new();
+        self.texture_images
+            .values()
+            .filter(|view| counted.insert(Arc::as_ptr(view.image())))
+            .flat_map(|view| view.image().memory_requirements())
+            .map(|requirements| requirements.layout.size())
+            .sum()
     }
-
-    pub fn draw_on_subpass_image(
+    /// Records draw commands for the frame, or returns the previous frame's command buffer (along
+    /// with its vertex & index data) if nothing that affects them has changed. See `draw_egui` for
+    /// `strict`.
+    fn record_egui(
         &mut self,
+        scale_factor: f32,
         clipped_meshes: &[ClippedPrimitive],
         textures_delta: &TexturesDelta,
-        scale_factor: f32,
         framebuffer_dimensions: [u32; 2],
-    ) -> Arc<SecondaryAutoCommandBuffer> {
-        self.update_textures(&textures_delta.set);
+        strict: bool,
+    ) -> Result<Arc<SecondaryAutoCommandBuffer>, DrawError> {
+        // Reused commands would write into stale timestamp queries
+        let hash = match self.gpu_timer {
+            Some(_) => None,
+            None => self.frame_hash(scale_factor, clipped_meshes, framebuffer_dimensions),
+        };
+        if let (Some(hash), Some(cached)) = (hash, &self.cached_frame) {
+            if hash == cached.hash
+                && cached.matches(
+                    self.textures_generation,
+                    scale_factor,
+                    clipped_meshes,
+                    framebuffer_dimensions,
+                    strict,
+                )
+            {
+                // Texture contents may still have been updated in place.
+                self.last_frame_changed = !textures_delta.is_empty();
+                self.stats = RenderStats {
+                    texture_upload_bytes: self.stats.texture_upload_bytes,
+                    reused_commands: true,
+                    ..cached.stats
+                };
+                return Ok(cached.command_buffer.clone());
+            }
+        }
+        self.last_frame_changed = true;
         let mut builder = self.create_secondary_command_buffer_builder();
-        self.draw_egui(scale_factor, clipped_meshes, framebuffer_dimensions, &mut builder);
+        self.draw_egui(scale_factor, clipped_meshes, framebuffer_dimensions, strict, &mut builder)?;
         let buffer = builder.build().unwrap();
-        for &id in &textures_delta.free {
-            self.unregister_image(id);
+        self.cached_frame = hash.map(|hash| CachedFrame {
+            hash,
+            textures_generation: self.textures_generation,
+            scale_factor,
+            framebuffer_dimensions,
+            strict,
+            mesh_count: clipped_meshes.len(),
+            index_count: index_count(clipped_meshes),
+            command_buffer: buffer.clone(),
+            stats: self.stats,
+        });
+        Ok(buffer)
+    }
+
+    /// Hashes everything that affects the recorded draw commands of a frame.
+    /// None if the frame contains paint callbacks, whose commands can't be reused.
+    fn frame_hash(
+        &self,
+        scale_factor: f32,
+        clipped_meshes: &[ClippedPrimitive],
+        framebuffer_dimensions: [u32; 2],
+    ) -> Option<u64> {
+        let mut hasher = AHasher::default();
+        self.textures_generation.hash(&mut hasher);
+        scale_factor.to_bits().hash(&mut hasher);
+        framebuffer_dimensions.hash(&mut hasher);
+        for ClippedPrimitive { clip_rect, primitive } in clipped_meshes {
+            let Primitive::Mesh(mesh) = primitive else {
+                return None;
+            };
+            [clip_rect.min.x, clip_rect.min.y, clip_rect.max.x, clip_rect.max.y]
+                .map(f32::to_bits)
+                .hash(&mut hasher);
+            mesh.texture_id.hash(&mut hasher);
+            mesh.indices.hash(&mut hasher);
+            for vertex in &mesh.vertices {
+                [vertex.pos.x, vertex.pos.y, vertex.uv.x, vertex.uv.y]
+                    .map(f32::to_bits)
+                    .hash(&mut hasher);
+                vertex.color.to_array().hash(&mut hasher);
+            }
         }
-        buffer
+        Some(hasher.finish())
     }
+
     /// Uploads all meshes in bulk. They will be available in the same order, packed.
     /// None if no vertices or no indices.
     fn upload_meshes(
         &mut self,
         clipped_meshes: &[ClippedPrimitive],
     ) -> Option<(VertexBuffer, IndexBuffer)> {
+        profile_scope!("Renderer::upload_meshes", primitives = clipped_meshes.len());
         use egui::epaint::Vertex;
         type Index = u32;
         const VERTEX_ALIGN: DeviceAlignment = DeviceAlignment::of::<Vertex>();
@@ -737,6 +2405,8 @@ impl Renderer {
         // Allocate a buffer which can hold both packed arrays:
         let layout = DeviceLayout::new(total_size_bytes, VERTEX_ALIGN.max(INDEX_ALIGN)).unwrap();
         let buffer = self.vertex_index_buffer_pool.allocate(layout).unwrap();
+        // The pool cycles through a few arenas, cheap enough to (re)name whichever we got
+        self.set_debug_name(buffer.buffer().as_ref(), "egui vertex/index arena");
 
         // We must put the items with stricter align *first* in the packed buffer.
         // Correct at time of writing, but assert in case that changes.
@@ -770,13 +2440,56 @@ impl Renderer {
         Some((vertices, indices))
     }
 
+    /// Draws the frame within the "egui" debug label and the pass timestamps. With `strict`, the
+    /// `Error` policies fail drawing, otherwise they skip like the `Skip` policies. On errors, the
+    /// label is still closed and the frame's timestamps are discarded.
     fn draw_egui(
         &mut self,
         scale_factor: f32,
         clipped_meshes: &[ClippedPrimitive],
         framebuffer_dimensions: [u32; 2],
+        strict: bool,
         builder: &mut AutoCommandBufferBuilder<SecondaryAutoCommandBuffer>,
-    ) {
+    ) -> Result<(), DrawError> {
+        profile_scope!("Renderer::draw_egui", primitives = clipped_meshes.len());
+        self.begin_debug_label(builder, "egui");
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.write_pass_start(builder);
+        }
+        let result = self.draw_primitives(
+            scale_factor,
+            clipped_meshes,
+            framebuffer_dimensions,
+            strict,
+            builder,
+        );
+        if let Some(timer) = &mut self.gpu_timer {
+            match result {
+                Ok(()) => timer.write_pass_end(builder),
+                Err(_) => timer.discard_frame(),
+            }
+        }
+        self.end_debug_label(builder);
+        result
+    }
+
+    /// Records the draw commands of all primitives, see `draw_egui`
+    fn draw_primitives(
+        &mut self,
+        scale_factor: f32,
+        clipped_meshes: &[ClippedPrimitive],
+        framebuffer_dimensions: [u32; 2],
+        strict: bool,
+        builder: &mut AutoCommandBufferBuilder<SecondaryAutoCommandBuffer>,
+    ) -> Result<(), DrawError> {
+        let missing_texture_policy = match self.settings.missing_texture_policy {
+            MissingTexturePolicy::Error if !strict => MissingTexturePolicy::Skip,
+            policy => policy,
+        };
+        let unsupported_callback_policy = match self.settings.unsupported_callback_policy {
+            UnsupportedCallbackPolicy::Error if !strict => UnsupportedCallbackPolicy::Skip,
+            policy => policy,
+        };
         let push_constants = vs::PushConstants {
             screen_size: [
                 framebuffer_dimensions[0] as f32 / scale_factor,
@@ -786,6 +2499,30 @@ impl Renderer {
         };
 
         let mesh_buffers = self.upload_meshes(clipped_meshes);
+        // A subpass' attachment holds the app's scene, which must be kept
+        if self.settings.debug_mode == RenderDebugMode::Overdraw && self.render_pass.is_some() {
+            builder
+                .clear_attachments(
+                    [ClearAttachment::Color {
+                        color_attachment: 0,
+                        clear_value: ClearColorValue::Float([0.0, 0.0, 0.0, 1.0]),
+                    }]
+                    .into_iter()
+                    .collect(),
+                    [ClearRect {
+                        offset: [0, 0],
+                        extent: framebuffer_dimensions,
+                        array_layers: 0..1,
+                    }]
+                    .into_iter()
+                    .collect(),
+                )
+                .unwrap();
+        }
+        // Draw calls and scissors the debug visualization repeats after the GUI is drawn
+        let collect_debug = self.settings.debug_mode != RenderDebugMode::Off;
+        let mut debug_draws = Vec::new();
+        let mut debug_scissors = Vec::new();
 
         // Current position of renderbuffers, advances as meshes are consumed.
         let mut vertex_cursor = 0;
@@ -797,7 +2534,10 @@ impl Renderer {
         // egui already makes the optimization that draws with identical resources are merged into one,
         // so every mesh changes usually one or possibly both of these.
         let mut current_rect = None;
+        let mut current_scissor = Scissor::default();
         let mut current_texture = None;
+        // Inspected images switch to their own shader variant
+        let mut bound_pipeline = self.pipeline.clone();
 
         for ClippedPrimitive { clip_rect, primitive } in clipped_meshes {
             match primitive {
@@ -844,25 +2584,63 @@ impl Renderer {
                             .unwrap()
                             .push_constants(self.pipeline.layout().clone(), 0, push_constants)
                             .unwrap();
+                        bound_pipeline = self.pipeline.clone();
                     }
                     // Find and bind image, if different.
                     if current_texture != Some(mesh.texture_id) {
-                        if self.texture_desc_sets.get(&mesh.texture_id).is_none() {
-                            eprintln!("This texture no longer exists {:?}", mesh.texture_id);
-                            continue;
-                        }
+                        let (desc_set, pipeline) = match self
+                            .texture_desc_sets
+                            .get(&mesh.texture_id)
+                        {
+                            Some(desc_set) => (desc_set, self.texture_pipeline(mesh.texture_id)),
+                            None => match missing_texture_policy {
+                                MissingTexturePolicy::Skip => {
+                                    warn_rate_limited!(
+                                        "Texture {:?} no longer exists, skipping its meshes",
+                                        mesh.texture_id
+                                    );
+                                    // Consume the mesh and skip it.
+                                    index_cursor += mesh.indices.len() as u32;
+                                    vertex_cursor += mesh.vertices.len() as u32;
+                                    continue;
+                                }
+                                MissingTexturePolicy::Placeholder => {
+                                    warn_rate_limited!(
+                                        "Texture {:?} no longer exists, drawing a placeholder",
+                                        mesh.texture_id
+                                    );
+                                    // Drawn like any egui texture, also in place of videos
+                                    // without a frame and their own pipelines
+                                    (self.placeholder_desc_set.as_ref().unwrap(), &self.pipeline)
+                                }
+                                MissingTexturePolicy::Error => {
+                                    return Err(DrawError::MissingTexture(mesh.texture_id));
+                                }
+                            },
+                        };
                         current_texture = Some(mesh.texture_id);
 
-                        let desc_set = self.texture_desc_sets.get(&mesh.texture_id).unwrap();
-
+                        if !Arc::ptr_eq(pipeline, &bound_pipeline) {
+                            builder.bind_pipeline_graphics(pipeline.clone()).unwrap();
+                            if Arc::ptr_eq(pipeline, &self.pipeline) {
+                                builder
+                                    .push_constants(pipeline.layout().clone(), 0, push_constants)
+                                    .unwrap();
+                            }
+                            bound_pipeline = pipeline.clone();
+                        }
+                        if !Arc::ptr_eq(pipeline, &self.pipeline) {
+                            self.push_texture_constants(builder, mesh.texture_id, push_constants);
+                        }
                         builder
                             .bind_descriptor_sets(
                                 PipelineBindPoint::Graphics,
-                                self.pipeline.layout().clone(),
+                                bound_pipeline.layout().clone(),
                                 0,
                                 desc_set.clone(),
                             )
                             .unwrap();
+                        self.stats.descriptor_set_binds += 1;
                     };
                     // Calculate and set scissor, if different
                     if current_rect != Some(*clip_rect) {
@@ -871,19 +2649,38 @@ impl Renderer {
                             self.get_rect_scissor(scale_factor, framebuffer_dimensions, *clip_rect);
 
                         builder.set_scissor(0, [new_scissor].into_iter().collect()).unwrap();
+                        self.stats.scissor_changes += 1;
+                        current_scissor = new_scissor;
+                        if collect_debug {
+                            debug_scissors.push(new_scissor);
+                        }
                     }
 
-                    unsafe {
-                        // All set up to draw!
-                        builder
-                            .draw_indexed(
-                                mesh.indices.len() as u32,
-                                1,
-                                index_cursor,
-                                vertex_cursor as i32,
-                                0,
-                            )
-                            .unwrap();
+                    // The overdraw heatmap replaces regular drawing
+                    if self.settings.debug_mode != RenderDebugMode::Overdraw {
+                        unsafe {
+                            // All set up to draw!
+                            builder
+                                .draw_indexed(
+                                    mesh.indices.len() as u32,
+                                    1,
+                                    index_cursor,
+                                    vertex_cursor as i32,
+                                    0,
+                                )
+                                .unwrap();
+                        }
+                        self.stats.draw_calls += 1;
+                        self.stats.vertices += mesh.vertices.len();
+                        self.stats.indices += mesh.indices.len();
+                    }
+                    if collect_debug {
+                        debug_draws.push(DebugDraw {
+                            scissor: current_scissor,
+                            index_count: mesh.indices.len() as u32,
+                            first_index: index_cursor,
+                            vertex_offset: vertex_cursor as i32,
+                        });
                     }
                     // Consume this mesh for next iteration
                     index_cursor += mesh.indices.len() as u32;
@@ -893,11 +2690,18 @@ impl Renderer {
                     if callback.rect.is_positive() {
                         let Some(callback_fn) = callback.callback.downcast_ref::<CallbackFn>()
                         else {
-                            println!(
-                                "Warning: Unsupported render callback. Expected \
-                                 egui_winit_vulkano::CallbackFn"
-                            );
-                            continue;
+                            match unsupported_callback_policy {
+                                UnsupportedCallbackPolicy::Skip => {
+                                    warn_rate_limited!(
+                                        "Unsupported paint callback, expected \
+                                         egui_winit_vulkano::CallbackFn"
+                                    );
+                                    continue;
+                                }
+                                UnsupportedCallbackPolicy::Error => {
+                                    return Err(DrawError::UnsupportedCallback);
+                                }
+                            }
                         };
 
                         let rect_min_x = scale_factor * callback.rect.min.x;
@@ -909,6 +2713,11 @@ impl Renderer {
                         let rect_min_y = rect_min_y.round();
                         let rect_max_x = rect_max_x.round();
                         let rect_max_y = rect_max_y.round();
+                        let scissor =
+                            self.get_rect_scissor(scale_factor, framebuffer_dimensions, *clip_rect);
+                        if collect_debug {
+                            debug_scissors.push(scissor);
+                        }
 
                         builder
                             .set_viewport(
@@ -922,16 +2731,7 @@ impl Renderer {
                                 .collect(),
                             )
                             .unwrap()
-                            .set_scissor(
-                                0,
-                                [self.get_rect_scissor(
-                                    scale_factor,
-                                    framebuffer_dimensions,
-                                    *clip_rect,
-                                )]
-                                .into_iter()
-                                .collect(),
-                            )
+                            .set_scissor(0, [scissor].into_iter().collect())
                             .unwrap();
 
                         let info = egui::PaintCallbackInfo {
@@ -940,10 +2740,19 @@ impl Renderer {
                             pixels_per_point: scale_factor,
                             screen_size_px: framebuffer_dimensions,
                         };
+                        self.begin_debug_label(builder, "egui paint callback");
+                        let timed_callback =
+                            self.gpu_timer.as_mut().and_then(|t| t.write_callback_start(builder));
                         (callback_fn.f)(
                             info,
                             &mut CallbackContext { builder, resources: self.render_resources() },
                         );
+                        if let (Some(timer), Some(index)) = (&self.gpu_timer, timed_callback) {
+                            timer.write_callback_end(builder, index);
+                        }
+                        self.end_debug_label(builder);
+                        self.stats.paint_callbacks += 1;
+                        self.stats.scissor_changes += 1;
 
                         // The user could have done much here - rebind pipes, set views, bind things, etc.
                         // Mark all state as lost so that next mesh rebinds everything to a known state.
@@ -954,6 +2763,105 @@ impl Renderer {
                 }
             }
         }
+        if collect_debug {
+            self.draw_debug_overlay(
+                builder,
+                mesh_buffers,
+                &debug_draws,
+                &debug_scissors,
+                push_constants.screen_size,
+                framebuffer_dimensions,
+            );
+        }
+        Ok(())
+    }
+
+    /// Draws the current `RenderDebugMode` over the already drawn GUI
+    fn draw_debug_overlay(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<SecondaryAutoCommandBuffer>,
+        mesh_buffers: Option<(VertexBuffer, IndexBuffer)>,
+        draws: &[DebugDraw],
+        scissors: &[Scissor],
+        screen_size: [f32; 2],
+        framebuffer_dimensions: [u32; 2],
+    ) {
+        let Some(pipelines) = &self.debug_pipelines else {
+            return;
+        };
+        self.begin_debug_label(builder, "egui debug overlay");
+        let viewport = Viewport {
+            offset: [0.0, 0.0],
+            extent: [framebuffer_dimensions[0] as f32, framebuffer_dimensions[1] as f32],
+            depth_range: 0.0..=1.0,
+        };
+        // Distinct colors for consecutive draws, spread around the hue circle
+        let color = |index: usize, alpha: f32| {
+            egui::ecolor::Hsva::new((index as f32 * 0.618_034).fract(), 0.8, 1.0, alpha)
+                .to_rgba_unmultiplied()
+        };
+
+        if self.settings.debug_mode == RenderDebugMode::ScissorRects {
+            let pipeline = &pipelines.scissor_rects;
+            builder
+                .bind_pipeline_graphics(pipeline.clone())
+                .unwrap()
+                .set_viewport(0, [viewport].into_iter().collect())
+                .unwrap();
+            for (index, scissor) in scissors.iter().enumerate() {
+                let push_constants = debug_rect_fs::PushConstants {
+                    rect: [
+                        scissor.offset[0] as f32,
+                        scissor.offset[1] as f32,
+                        (scissor.offset[0] + scissor.extent[0]) as f32,
+                        (scissor.offset[1] + scissor.extent[1]) as f32,
+                    ],
+                    color: color(index, 1.0),
+                };
+                builder
+                    .set_scissor(0, [*scissor].into_iter().collect())
+                    .unwrap()
+                    .push_constants(pipeline.layout().clone(), 0, push_constants)
+                    .unwrap();
+                // A full screen triangle, clipped to the scissor
+                unsafe { builder.draw(3, 1, 0, 0) }.unwrap();
+            }
+        } else if let Some((vertices, indices)) = mesh_buffers {
+            let (pipeline, flat_color) = match self.settings.debug_mode {
+                RenderDebugMode::Batches => (&pipelines.batches, None),
+                RenderDebugMode::Wireframe => {
+                    (pipelines.wireframe.as_ref().unwrap(), Some([0.0, 1.0, 1.0, 0.8]))
+                }
+                // Adds up, so that each covering triangle makes the pixel brighter
+                _ => (&pipelines.overdraw, Some([0.15, 0.05, 0.02, 1.0])),
+            };
+            builder
+                .bind_pipeline_graphics(pipeline.clone())
+                .unwrap()
+                .bind_index_buffer(indices)
+                .unwrap()
+                .bind_vertex_buffers(0, [vertices])
+                .unwrap()
+                .set_viewport(0, [viewport].into_iter().collect())
+                .unwrap();
+            for (index, draw) in draws.iter().enumerate() {
+                let push_constants = debug_vs::PushConstants {
+                    color: flat_color.unwrap_or_else(|| color(index, 0.35)),
+                    screen_size,
+                };
+                builder
+                    .set_scissor(0, [draw.scissor].into_iter().collect())
+                    .unwrap()
+                    .push_constants(pipeline.layout().clone(), 0, push_constants)
+                    .unwrap();
+                unsafe {
+                    builder
+                        .draw_indexed(draw.index_count, 1, draw.first_index, draw.vertex_offset, 0)
+                        .unwrap();
+                }
+            }
+        }
+        self.end_debug_label(builder);
     }
 
     pub fn render_resources(&self) -> RenderResources<'_> {
@@ -970,8 +2878,37 @@ impl Renderer {
         self.gfx_queue.clone()
     }
 
-    pub fn allocators(&self) -> &Allocators {
-        &self.allocators
+    /// Uploads tightly packed pixel bytes to a new image without registering it, e.g. for
+    /// `replace_image_view`
+    #[cfg(feature = "svg")]
+    pub fn create_image_from_bytes(
+        &mut self,
+        image_byte_data: &[u8],
+        dimensions: [u32; 2],
+        format: Format,
+    ) -> Result<Arc<ImageView>, ImageCreationError> {
+        let image = self.submit_upload(|allocators, cbb| {
+            record_texture_from_bytes(allocators, cbb, image_byte_data, dimensions, format)
+        })?;
+        self.stats.texture_upload_bytes += image_byte_data.len() as u64;
+        Ok(image)
+    }
+}
+
+/// Copies `from` (rows of `from_width` texels) into `into` (rows of `into_width` texels) at `pos`.
+fn blit_region<T: Copy>(
+    into: &mut [T],
+    into_width: usize,
+    from: &[T],
+    from_width: usize,
+    pos: [usize; 2],
+) {
+    if from_width == 0 {
+        return;
+    }
+    for (row, from_row) in from.chunks_exact(from_width).enumerate() {
+        let start = (pos[1] + row) * into_width + pos[0];
+        into[start..start + from_width].copy_from_slice(from_row);
     }
 }
 
@@ -1115,3 +3052,280 @@ void main() {
 }"
     }
 }
+
+mod debug_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+#version 450
+
+layout(location = 0) in vec2 position;
+
+layout(location = 0) out vec4 v_color;
+
+layout(push_constant) uniform PushConstants {
+    vec4 color;
+    vec2 screen_size;
+} push_constants;
+
+void main() {
+    gl_Position = vec4(
+        2.0 * position.x / push_constants.screen_size.x - 1.0,
+        2.0 * position.y / push_constants.screen_size.y - 1.0,
+        0.0, 1.0
+    );
+    v_color = push_constants.color;
+}"
+    }
+}
+
+mod debug_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+#version 450
+
+layout(location = 0) in vec4 v_color;
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+    f_color = v_color;
+}"
+    }
+}
+
+mod debug_rect_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+#version 450
+
+// A triangle covering the whole viewport
+void main() {
+    vec2 uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    gl_Position = vec4(uv * 2.0 - 1.0, 0.0, 1.0);
+}"
+    }
+}
+
+mod debug_rect_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+#version 450
+
+layout(location = 0) out vec4 f_color;
+
+// Rect as min x, min y, max x, max y in pixels
+layout(push_constant) uniform PushConstants {
+    vec4 rect;
+    vec4 color;
+} push_constants;
+
+void main() {
+    vec2 from_min = gl_FragCoord.xy - push_constants.rect.xy;
+    vec2 from_max = push_constants.rect.zw - gl_FragCoord.xy;
+    float edge_distance = min(min(from_min.x, from_min.y), min(from_max.x, from_max.y));
+    // Keep a 2 pixel outline
+    if (edge_distance > 2.0) {
+        discard;
+    }
+    f_color = push_constants.color;
+}"
+    }
+}
+
+mod inspect_float_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/inspect.frag",
+        define: [("SAMPLER", "sampler2D")],
+    }
+}
+
+mod inspect_uint_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/inspect.frag",
+        define: [("SAMPLER", "usampler2D")],
+    }
+}
+
+mod inspect_sint_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/inspect.frag",
+        define: [("SAMPLER", "isampler2D")],
+    }
+}
+
+mod inspect_float_3d_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/inspect.frag",
+        define: [("SAMPLER", "sampler3D"), ("DIM_3D", "1")],
+    }
+}
+
+mod inspect_uint_3d_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/inspect.frag",
+        define: [("SAMPLER", "usampler3D"), ("DIM_3D", "1")],
+    }
+}
+
+mod inspect_sint_3d_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/inspect.frag",
+        define: [("SAMPLER", "isampler3D"), ("DIM_3D", "1")],
+    }
+}
+
+// Fallback for videos without a YCbCr conversion, converting the separately sampled planes
+mod yuv_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+#version 450
+
+layout(location = 0) in vec4 v_color;
+layout(location = 1) in vec2 v_tex_coords;
+
+layout(location = 0) out vec4 f_color;
+
+layout(binding = 0, set = 0) uniform sampler2D y_plane;
+// Interleaved UV of two plane formats, U otherwise
+layout(binding = 1, set = 0) uniform sampler2D u_plane;
+layout(binding = 2, set = 0) uniform sampler2D v_plane;
+
+layout(push_constant) uniform PushConstants {
+    vec2 screen_size;
+    int output_in_linear_colorspace;
+    int two_plane;
+    // Factors of Cr to R, Cb to G, Cr to G and Cb to B
+    vec4 coefficients;
+    int full_range;
+} push_constants;
+
+// 0-1 linear  from  0-1 sRGB
+vec4 linear_from_srgba(vec4 srgb) {
+    bvec3 cutoff = lessThan(srgb.rgb, vec3(0.04045));
+    vec3 lower = srgb.rgb / vec3(12.92);
+    vec3 higher = pow((srgb.rgb + vec3(0.055)) / vec3(1.055), vec3(2.4));
+    return vec4(mix(higher, lower, vec3(cutoff)), srgb.a);
+}
+
+void main() {
+    float y = texture(y_plane, v_tex_coords).r;
+    vec2 cb_cr = push_constants.two_plane == 1
+        ? texture(u_plane, v_tex_coords).rg
+        : vec2(texture(u_plane, v_tex_coords).r, texture(v_plane, v_tex_coords).r);
+    if (push_constants.full_range == 1) {
+        cb_cr -= 0.5;
+    } else {
+        y = (y - 16.0 / 255.0) * (255.0 / 219.0);
+        cb_cr = (cb_cr - 128.0 / 255.0) * (255.0 / 224.0);
+    }
+    vec4 k = push_constants.coefficients;
+    vec3 rgb = vec3(y + k.x * cb_cr.y, y - k.y * cb_cr.x - k.z * cb_cr.y, y + k.w * cb_cr.x);
+
+    // Video is gamma encoded like egui's colors, so it is used as is
+    vec4 color = v_color * vec4(clamp(rgb, 0.0, 1.0), 1.0);
+    if (push_constants.output_in_linear_colorspace == 1) {
+        color = linear_from_srgba(color);
+    }
+    f_color = color;
+}"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use egui::{epaint::Mesh, pos2, Color32};
+    use vulkano::{
+        device::{Device, DeviceCreateInfo, QueueCreateInfo, QueueFlags},
+        instance::{Instance, InstanceCreateInfo},
+        VulkanLibrary,
+    };
+
+    use super::*;
+
+    /// A graphics queue of the first device that has one, None without a Vulkan driver
+    fn graphics_queue() -> Option<Arc<Queue>> {
+        let library = VulkanLibrary::new().ok()?;
+        let instance = Instance::new(library, InstanceCreateInfo::default()).ok()?;
+        let (physical_device, queue_family_index) =
+            instance.enumerate_physical_devices().ok()?.find_map(|device| {
+                let index = device
+                    .queue_family_properties()
+                    .iter()
+                    .position(|family| family.queue_flags.intersects(QueueFlags::GRAPHICS))?;
+                Some((device, index as u32))
+            })?;
+        let (_, mut queues) = Device::new(
+            physical_device,
+            DeviceCreateInfo {
+                queue_create_infos: vec![QueueCreateInfo {
+                    queue_family_index,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+        )
+        .ok()?;
+        queues.next()
+    }
+
+    #[test]
+    fn placeholder_replaces_video_without_frame() {
+        let Some(queue) = graphics_queue() else {
+            // Nothing to draw with
+            return;
+        };
+        let format = Format::R8G8B8A8_UNORM;
+        let mut renderer =
+            Renderer::new_with_render_pass(queue.clone(), format, false, SampleCount::Sample1);
+        renderer.set_missing_texture_policy(MissingTexturePolicy::Placeholder);
+        let id = renderer
+            .register_video(YuvFormat::Nv12, [16, 16], YuvColorSpace::Bt709, YuvRange::Limited)
+            .unwrap();
+
+        let rect = Rect::from_min_max(pos2(0.0, 0.0), pos2(16.0, 16.0));
+        let mut mesh = Mesh::with_texture(id);
+        mesh.add_rect_with_uv(
+            rect,
+            Rect::from_min_max(pos2(0.0, 0.0), pos2(1.0, 1.0)),
+            Color32::WHITE,
+        );
+        let primitives = [ClippedPrimitive { clip_rect: rect, primitive: Primitive::Mesh(mesh) }];
+        let target = Image::new(
+            renderer.allocators.memory.clone(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format,
+                extent: [16, 16, 1],
+                usage: ImageUsage::COLOR_ATTACHMENT,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )
+        .unwrap();
+        renderer
+            .draw_on_image(
+                &primitives,
+                &TexturesDelta::default(),
+                1.0,
+                sync::now(queue.device().clone()),
+                ImageView::new_default(target).unwrap(),
+                true,
+            )
+            .unwrap()
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+    }
+}
@@ -0,0 +1,56 @@
+#version 450
+
+// Fragment shader of the image inspector, compiled once per sampler type by defining
+// SAMPLER (e.g. usampler2D) and, for 3D images, DIM_3D.
+
+layout(location = 0) in vec4 v_color;
+layout(location = 1) in vec2 v_tex_coords;
+
+layout(location = 0) out vec4 f_color;
+
+layout(binding = 0, set = 0) uniform SAMPLER inspected_texture;
+
+layout(push_constant) uniform PushConstants {
+    // Shared with the vertex shader
+    vec2 screen_size;
+    int output_in_linear_colorspace;
+    // 0-3 shows a single channel as grayscale, 4 shows RGB, 5 shows RGBA
+    int channel;
+    // Values mapped to 0 and 1
+    vec2 value_range;
+    // Normalized depth of the slice shown of a 3D image
+    float slice;
+} push_constants;
+
+// 0-1 linear  from  0-1 sRGB
+vec4 linear_from_srgba(vec4 srgb) {
+    bvec3 cutoff = lessThan(srgb.rgb, vec3(0.04045));
+    vec3 lower = srgb.rgb / vec3(12.92);
+    vec3 higher = pow((srgb.rgb + vec3(0.055)) / vec3(1.055), vec3(2.4));
+    return vec4(mix(higher, lower, vec3(cutoff)), srgb.a);
+}
+
+void main() {
+#ifdef DIM_3D
+    vec4 value = vec4(texture(inspected_texture, vec3(v_tex_coords, push_constants.slice)));
+#else
+    vec4 value = vec4(texture(inspected_texture, v_tex_coords));
+#endif
+    vec2 range = push_constants.value_range;
+    value = clamp((value - range.x) / max(range.y - range.x, 1e-20), 0.0, 1.0);
+
+    vec4 color;
+    if (push_constants.channel < 4) {
+        color = vec4(vec3(value[push_constants.channel]), 1.0);
+    } else if (push_constants.channel == 4) {
+        color = vec4(value.rgb, 1.0);
+    } else {
+        color = value;
+    }
+    // Values are shown as they are, i.e. as if they were gamma encoded like egui's colors
+    color = v_color * color;
+    if (push_constants.output_in_linear_colorspace == 1) {
+        color = linear_from_srgba(color);
+    }
+    f_color = color;
+}
@@ -0,0 +1,103 @@
+// Copyright (c) 2021 Okko Hakola
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use std::fmt;
+
+use resvg::{tiny_skia, usvg};
+use vulkano::image::sampler::SamplerCreateInfo;
+
+/// Size to rasterize an SVG at, see `Gui::register_user_svg`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SvgSize {
+    /// The SVG's own size in points. Re-rasterized when `pixels_per_point` changes.
+    Original,
+    /// Size in points. Re-rasterized when `pixels_per_point` changes.
+    Points(egui::Vec2),
+    /// Fixed size in pixels, never re-rasterized
+    Pixels([u32; 2]),
+}
+
+/// Why an SVG couldn't be registered or rasterized, see `Gui::register_user_svg`
+#[derive(Debug)]
+pub enum SvgError {
+    /// The data isn't a valid SVG
+    Parse(usvg::Error),
+    /// The SVG would be rasterized to zero (or unreasonably many) pixels
+    InvalidSize([u32; 2]),
+}
+
+impl fmt::Display for SvgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SvgError::Parse(_) => write!(f, "failed to parse the SVG"),
+            SvgError::InvalidSize(size) => {
+                write!(f, "can't rasterize the SVG at {}x{} pixels", size[0], size[1])
+            }
+        }
+    }
+}
+
+impl std::error::Error for SvgError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SvgError::Parse(err) => Some(err),
+            SvgError::InvalidSize(_) => None,
+        }
+    }
+}
+
+/// A parsed SVG registered as a user texture, kept to rasterize it again at another scale
+pub(crate) struct SvgTexture {
+    tree: usvg::Tree,
+    size: SvgSize,
+    // For registering the texture again on a new device
+    pub sampler_create_info: SamplerCreateInfo,
+    // Scale of the last rasterization
+    pixels_per_point: f32,
+}
+
+impl SvgTexture {
+    pub fn parse(
+        svg_data: &[u8],
+        size: SvgSize,
+        sampler_create_info: SamplerCreateInfo,
+    ) -> Result<Self, SvgError> {
+        let tree =
+            usvg::Tree::from_data(svg_data, &usvg::Options::default()).map_err(SvgError::Parse)?;
+        Ok(Self { tree, size, sampler_create_info, pixels_per_point: 0.0 })
+    }
+
+    /// Whether the texture is out of date at this scale
+    pub fn needs_rasterize(&self, pixels_per_point: f32) -> bool {
+        !matches!(self.size, SvgSize::Pixels(_)) && pixels_per_point != self.pixels_per_point
+    }
+
+    /// Rasterizes to premultiplied RGBA8 pixels, like egui's own color images
+    pub fn rasterize(&mut self, pixels_per_point: f32) -> Result<(Vec<u8>, [u32; 2]), SvgError> {
+        let points = match self.size {
+            SvgSize::Original => egui::vec2(self.tree.size().width(), self.tree.size().height()),
+            SvgSize::Points(points) => points,
+            SvgSize::Pixels(pixels) => egui::vec2(pixels[0] as f32, pixels[1] as f32),
+        };
+        let scale = match self.size {
+            SvgSize::Pixels(_) => 1.0,
+            _ => pixels_per_point,
+        };
+        let dimensions = [(points.x * scale).round() as u32, (points.y * scale).round() as u32];
+        let mut pixmap = tiny_skia::Pixmap::new(dimensions[0], dimensions[1])
+            .ok_or(SvgError::InvalidSize(dimensions))?;
+        let transform = tiny_skia::Transform::from_scale(
+            dimensions[0] as f32 / self.tree.size().width(),
+            dimensions[1] as f32 / self.tree.size().height(),
+        );
+        resvg::render(&self.tree, transform, &mut pixmap.as_mut());
+        self.pixels_per_point = pixels_per_point;
+        Ok((pixmap.take(), dimensions))
+    }
+}
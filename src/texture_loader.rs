@@ -0,0 +1,218 @@
+// Copyright (c) 2021 Okko Hakola
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use ahash::AHashMap;
+use egui::{
+    load::{
+        Bytes, BytesPoll, LoadError, SizeHint, SizedTexture, TextureLoadResult, TextureLoader,
+        TexturePoll,
+    },
+    TextureFilter, TextureId, TextureOptions, TextureWrapMode, Vec2,
+};
+use vulkano::image::sampler::{Filter, SamplerAddressMode, SamplerCreateInfo};
+
+use crate::async_image::DecodePool;
+
+type Key = (String, TextureOptions);
+
+/// An image decoded by the loader, to be uploaded by `Gui` at the start of the next frame
+pub(crate) struct PendingUpload {
+    pub key: Key,
+    pub image: image::DynamicImage,
+}
+
+enum Entry {
+    Decoding,
+    Pending { size: Vec2 },
+    Ready { texture: SizedTexture, byte_size: usize },
+    Failed(String),
+}
+
+#[derive(Default)]
+struct State {
+    entries: AHashMap<Key, Entry>,
+    uploads: Vec<PendingUpload>,
+    // Textures of forgotten uris, to be unregistered by `Gui`
+    freed: Vec<TextureId>,
+}
+
+/// Texture loader for `ui.image(..)` & co. that decodes images with the `image` crate and has
+/// `Gui` upload them as user textures, instead of going through egui's managed textures.
+/// The loader itself can't reach the renderer, so uploads and frees are queued for `Gui`.
+/// Images are read and decoded on the threads of `pool`.
+pub(crate) struct VulkanoTextureLoader {
+    state: Arc<Mutex<State>>,
+    pool: Arc<DecodePool>,
+}
+
+/// Encoded image to be decoded off the UI thread
+enum EncodedImage {
+    Bytes(Bytes),
+    File(PathBuf),
+}
+
+impl VulkanoTextureLoader {
+    pub fn new(pool: Arc<DecodePool>) -> Self {
+        Self { state: Default::default(), pool }
+    }
+
+    /// Takes the images decoded since the last call
+    pub fn take_uploads(&self) -> Vec<PendingUpload> {
+        std::mem::take(&mut self.state.lock().unwrap().uploads)
+    }
+
+    /// Takes the textures of uris forgotten since the last call
+    pub fn take_freed(&self) -> Vec<TextureId> {
+        std::mem::take(&mut self.state.lock().unwrap().freed)
+    }
+
+    /// Stores the texture an upload ended up in. False if its uri was forgotten meanwhile, in
+    /// which case the texture should be unregistered again.
+    pub fn finish_upload(&self, key: Key, id: TextureId, byte_size: usize) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match state.entries.get_mut(&key) {
+            Some(entry @ Entry::Pending { .. }) => {
+                let Entry::Pending { size } = *entry else { unreachable!() };
+                *entry = Entry::Ready { texture: SizedTexture::new(id, size), byte_size };
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn forget_where(&self, mut forget: impl FnMut(&Key) -> bool) {
+        let state = &mut *self.state.lock().unwrap();
+        state.entries.retain(|key, entry| {
+            if !forget(key) {
+                return true;
+            }
+            if let Entry::Ready { texture, .. } = entry {
+                state.freed.push(texture.id);
+            }
+            false
+        });
+        // Pending uploads of forgotten uris no longer have an entry
+        let entries = &state.entries;
+        state.uploads.retain(|upload| entries.contains_key(&upload.key));
+    }
+}
+
+impl TextureLoader for VulkanoTextureLoader {
+    fn id(&self) -> &str {
+        egui::load::generate_loader_id!(VulkanoTextureLoader)
+    }
+
+    fn load(
+        &self,
+        ctx: &egui::Context,
+        uri: &str,
+        texture_options: TextureOptions,
+        _size_hint: SizeHint,
+    ) -> TextureLoadResult {
+        let key = (uri.to_owned(), texture_options);
+        if let Some(entry) = self.state.lock().unwrap().entries.get(&key) {
+            return match entry {
+                Entry::Decoding => Ok(TexturePoll::Pending { size: None }),
+                Entry::Pending { size } => Ok(TexturePoll::Pending { size: Some(*size) }),
+                Entry::Ready { texture, .. } => Ok(TexturePoll::Ready { texture: *texture }),
+                Entry::Failed(err) => Err(LoadError::Loading(err.clone())),
+            };
+        }
+
+        let encoded = match ctx.try_load_bytes(uri) {
+            Ok(BytesPoll::Ready { bytes, .. }) => EncodedImage::Bytes(bytes),
+            Ok(BytesPoll::Pending { size }) => return Ok(TexturePoll::Pending { size }),
+            // egui can only read files with the loaders of egui_extras, do it ourselves otherwise
+            Err(LoadError::NoMatchingBytesLoader) => match uri.strip_prefix("file://") {
+                Some(path) => EncodedImage::File(PathBuf::from(path)),
+                None => return Err(LoadError::NoMatchingBytesLoader),
+            },
+            Err(err) => return Err(err),
+        };
+        let format = match &encoded {
+            EncodedImage::Bytes(bytes) => image::guess_format(bytes),
+            EncodedImage::File(path) => image::ImageFormat::from_path(path),
+        };
+        // Leave formats we can't decode (e.g. SVG) to other loaders
+        let format = match format {
+            Ok(format) if format.reading_enabled() => format,
+            _ => return Err(LoadError::NotSupported),
+        };
+
+        self.state.lock().unwrap().entries.insert(key.clone(), Entry::Decoding);
+        let state = self.state.clone();
+        let ctx = ctx.clone();
+        self.pool.execute(move || {
+            let decoded = match encoded {
+                EncodedImage::Bytes(bytes) => image::load_from_memory_with_format(&bytes, format),
+                EncodedImage::File(path) => std::fs::read(path)
+                    .map_err(image::ImageError::IoError)
+                    .and_then(|bytes| image::load_from_memory_with_format(&bytes, format)),
+            };
+            let state = &mut *state.lock().unwrap();
+            // Forgotten while decoding
+            let Some(entry) = state.entries.get_mut(&key) else {
+                return;
+            };
+            match decoded {
+                Ok(image) => {
+                    let size = Vec2::new(image.width() as f32, image.height() as f32);
+                    *entry = Entry::Pending { size };
+                    state.uploads.push(PendingUpload { key, image });
+                }
+                Err(err) => *entry = Entry::Failed(err.to_string()),
+            }
+            // Uploaded at the start of the next frame
+            ctx.request_repaint();
+        });
+        Ok(TexturePoll::Pending { size: None })
+    }
+
+    fn forget(&self, uri: &str) {
+        self.forget_where(|(key_uri, _)| key_uri == uri);
+    }
+
+    fn forget_all(&self) {
+        self.forget_where(|_| true);
+    }
+
+    fn byte_size(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        let uploaded = state.entries.values().map(|entry| match entry {
+            Entry::Ready { byte_size, .. } => *byte_size,
+            _ => 0,
+        });
+        let pending = state.uploads.iter().map(|upload| upload.image.as_bytes().len());
+        uploaded.sum::<usize>() + pending.sum::<usize>()
+    }
+}
+
+/// Sampler matching egui's texture options. Mipmaps aren't generated, so `mipmap_mode` is ignored.
+pub(crate) fn sampler_create_info(options: TextureOptions) -> SamplerCreateInfo {
+    let filter = |filter| match filter {
+        TextureFilter::Nearest => Filter::Nearest,
+        TextureFilter::Linear => Filter::Linear,
+    };
+    let address_mode = match options.wrap_mode {
+        TextureWrapMode::ClampToEdge => SamplerAddressMode::ClampToEdge,
+        TextureWrapMode::Repeat => SamplerAddressMode::Repeat,
+        TextureWrapMode::MirroredRepeat => SamplerAddressMode::MirroredRepeat,
+    };
+    SamplerCreateInfo {
+        mag_filter: filter(options.magnification),
+        min_filter: filter(options.minification),
+        address_mode: [address_mode; 3],
+        ..Default::default()
+    }
+}
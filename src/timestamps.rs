@@ -0,0 +1,226 @@
+// Copyright (c) 2021 Okko Hakola
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use std::{ops::Range, sync::Arc, time::Duration};
+
+use vulkano::{
+    command_buffer::AutoCommandBufferBuilder,
+    device::Queue,
+    query::{QueryPool, QueryPoolCreateInfo, QueryResultFlags, QueryType},
+    sync::{GpuFuture, PipelineStage},
+};
+
+/// Number of frames whose queries are in use at once. A frame's results are read back when its
+/// queries are about to be reused, by which time the GPU has usually finished with them. If it
+/// hasn't, the frame is not timed, unless the queries are older than the frames in flight.
+const FRAMES_IN_FLIGHT: usize = 4;
+/// Paint callbacks timed per frame, any further callbacks are not timed.
+const MAX_TIMED_CALLBACKS: u32 = 16;
+/// Start & end of the whole pass, then start & end of each callback.
+const QUERIES_PER_FRAME: u32 = 2 + 2 * MAX_TIMED_CALLBACKS;
+
+/// GPU durations of a frame's GUI rendering, see `Gui::gpu_timings`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GpuTimings {
+    /// Time of all GUI draw commands, including paint callbacks
+    pub gui_pass: Duration,
+    /// Time of each paint callback in draw order. At most 16 callbacks are timed per frame.
+    pub paint_callbacks: Vec<Duration>,
+}
+
+#[derive(Default)]
+struct FrameQueries {
+    written: bool,
+    // Frame counter value when the queries were written
+    written_frame: u64,
+    callbacks: u32,
+    // Separately submitted reset of this frame's queries, kept alive until they're reused
+    reset_future: Option<Box<dyn GpuFuture>>,
+}
+
+/// Ring of timestamp queries written around the GUI pass and each paint callback
+pub(crate) struct GpuTimer {
+    query_pool: Arc<QueryPool>,
+    // Nanoseconds per timestamp tick
+    timestamp_period: f64,
+    timestamp_mask: u64,
+    frames: [FrameQueries; FRAMES_IN_FLIGHT],
+    current: usize,
+    // Frames begun so far
+    frame: u64,
+    // Frames the GPU may lag behind, queries written before that were never executed
+    frames_in_flight: u64,
+    // False if the current queries are still pending on the GPU, the frame isn't timed then
+    active: bool,
+    latest: Option<GpuTimings>,
+}
+
+impl GpuTimer {
+    /// None if the queue doesn't support timestamps
+    pub fn new(queue: &Arc<Queue>, frames_in_flight: u32) -> Option<GpuTimer> {
+        let device = queue.device();
+        let valid_bits = device.physical_device().queue_family_properties()
+            [queue.queue_family_index() as usize]
+            .timestamp_valid_bits
+            .filter(|&bits| bits > 0)?;
+        let query_pool = QueryPool::new(
+            device.clone(),
+            QueryPoolCreateInfo {
+                query_count: QUERIES_PER_FRAME * FRAMES_IN_FLIGHT as u32,
+                ..QueryPoolCreateInfo::query_type(QueryType::Timestamp)
+            },
+        )
+        .ok()?;
+        Some(GpuTimer {
+            query_pool,
+            timestamp_period: device.physical_device().properties().timestamp_period as f64,
+            timestamp_mask: u64::MAX >> (64 - valid_bits.min(64)),
+            frames: Default::default(),
+            current: 0,
+            frame: 0,
+            frames_in_flight: frames_in_flight as u64,
+            active: true,
+            latest: None,
+        })
+    }
+
+    /// Latest resolved timings, a few frames old
+    pub fn latest(&self) -> Option<&GpuTimings> {
+        self.latest.as_ref()
+    }
+
+    fn queries(&self) -> Range<u32> {
+        let start = self.current as u32 * QUERIES_PER_FRAME;
+        start..start + QUERIES_PER_FRAME
+    }
+
+    /// See `GuiConfig::frames_in_flight`
+    pub fn set_frames_in_flight(&mut self, frames_in_flight: u32) {
+        self.frames_in_flight = frames_in_flight as u64;
+    }
+
+    /// Moves on to the next frame's queries, reading back the results of the frame that used
+    /// them before. If those aren't available yet, the GPU may still be writing the queries, so
+    /// this frame isn't timed and they're read back when they come around again. Queries still
+    /// without results after more than `frames_in_flight` frames belong to a command buffer that
+    /// was never executed, and are reused without results.
+    pub fn next_frame(&mut self) {
+        self.current = (self.current + 1) % FRAMES_IN_FLIGHT;
+        self.frame += 1;
+        self.active = self.read_back()
+            || self.frame - self.frames[self.current].written_frame > self.frames_in_flight;
+        if self.active {
+            self.frames[self.current] = FrameQueries::default();
+        }
+    }
+
+    /// Reads back the results of the current queries. Returns false if they're still pending.
+    fn read_back(&mut self) -> bool {
+        let frame = &self.frames[self.current];
+        if !frame.written {
+            return true;
+        }
+        let start = self.queries().start;
+        let mut results = vec![0u64; 2 + 2 * frame.callbacks as usize];
+        let range = start..start + results.len() as u32;
+        // Without `WAIT`, true only once every query in the range has been written
+        let Ok(true) = self.query_pool.get_results(range, &mut results, QueryResultFlags::empty())
+        else {
+            return false;
+        };
+        let duration = |ticks: &[u64]| {
+            let ticks = ticks[1].wrapping_sub(ticks[0]) & self.timestamp_mask;
+            Duration::from_nanos((ticks as f64 * self.timestamp_period) as u64)
+        };
+        self.latest = Some(GpuTimings {
+            gui_pass: duration(&results[0..2]),
+            paint_callbacks: results[2..].chunks_exact(2).map(duration).collect(),
+        });
+        true
+    }
+
+    /// Whether this frame is timed, see `next_frame`
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Resets this frame's queries. Must be recorded outside of a render pass, before any writes,
+    /// and executed on the queue the writes are executed on.
+    pub fn reset<L>(&self, builder: &mut AutoCommandBufferBuilder<L>) {
+        if !self.active {
+            return;
+        }
+        // Safe: `next_frame` only activates queries that were never written, whose results were
+        // all available, or that were written by a command buffer that was never executed, so
+        // the GPU is done with them.
+        unsafe { builder.reset_query_pool(self.query_pool.clone(), self.queries()) }.unwrap();
+    }
+
+    /// Keeps a separately submitted `reset` alive until this frame's queries are reused
+    pub fn keep_reset_future(&mut self, future: Box<dyn GpuFuture>) {
+        self.frames[self.current].reset_future = Some(future);
+    }
+
+    fn write<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        query: u32,
+        stage: PipelineStage,
+    ) {
+        // Safe: the query has been reset by `reset` and is written only once per frame.
+        unsafe {
+            builder.write_timestamp(self.query_pool.clone(), self.queries().start + query, stage)
+        }
+        .unwrap();
+    }
+
+    pub fn write_pass_start<L>(&mut self, builder: &mut AutoCommandBufferBuilder<L>) {
+        if !self.active {
+            return;
+        }
+        self.write(builder, 0, PipelineStage::TopOfPipe);
+        let frame = &mut self.frames[self.current];
+        frame.written = true;
+        frame.written_frame = self.frame;
+    }
+
+    /// Forgets this frame's writes, for a frame whose commands won't be executed
+    pub fn discard_frame(&mut self) {
+        if self.active {
+            let frame = &mut self.frames[self.current];
+            frame.written = false;
+            frame.callbacks = 0;
+        }
+    }
+
+    pub fn write_pass_end<L>(&self, builder: &mut AutoCommandBufferBuilder<L>) {
+        if !self.active {
+            return;
+        }
+        self.write(builder, 1, PipelineStage::BottomOfPipe);
+    }
+
+    /// Returns the index of the callback's queries, None if this frame has none left
+    pub fn write_callback_start<L>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+    ) -> Option<u32> {
+        let index = self.frames[self.current].callbacks;
+        if !self.active || index >= MAX_TIMED_CALLBACKS {
+            return None;
+        }
+        self.write(builder, 2 + 2 * index, PipelineStage::TopOfPipe);
+        self.frames[self.current].callbacks += 1;
+        Some(index)
+    }
+
+    pub fn write_callback_end<L>(&self, builder: &mut AutoCommandBufferBuilder<L>, index: u32) {
+        self.write(builder, 3 + 2 * index, PipelineStage::BottomOfPipe);
+    }
+}
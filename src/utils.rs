@@ -10,18 +10,24 @@
 use std::sync::Arc;
 
 #[cfg(feature = "image")]
-use image::RgbaImage;
+use image::DynamicImage;
 use vulkano::{
     buffer::{AllocateBufferError, Buffer, BufferCreateInfo, BufferUsage},
     command_buffer::{
         allocator::{StandardCommandBufferAllocator, StandardCommandBufferAllocatorCreateInfo},
         AutoCommandBufferBuilder, CommandBufferUsage, CopyBufferToImageInfo,
-        PrimaryCommandBufferAbstract,
+        PrimaryAutoCommandBuffer, PrimaryCommandBufferAbstract,
     },
     descriptor_set::allocator::StandardDescriptorSetAllocator,
     device::{Device, Queue},
-    image::{view::ImageView, AllocateImageError, Image, ImageCreateInfo, ImageType, ImageUsage},
+    format::Format,
+    image::{
+        sampler::ComponentMapping,
+        view::{ImageView, ImageViewCreateInfo},
+        AllocateImageError, Image, ImageCreateInfo, ImageType, ImageUsage,
+    },
     memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+    sync::GpuFuture,
     Validated, ValidationError, VulkanError,
 };
 
@@ -31,6 +37,18 @@ pub enum ImageCreationError {
     AllocateImage(Validated<AllocateImageError>),
     AllocateBuffer(Validated<AllocateBufferError>),
     Validation(Box<ValidationError>),
+    #[cfg(feature = "image")]
+    Decode(image::ImageError),
+    #[cfg(feature = "ktx2")]
+    Ktx2(ktx2::ParseError),
+    #[cfg(feature = "dds")]
+    Dds(ddsfile::Error),
+    /// The texture's format can neither be sampled by the device nor decoded on the CPU
+    #[cfg(any(feature = "ktx2", feature = "dds"))]
+    UnsupportedFormat(String),
+    /// The container's data doesn't match its header
+    #[cfg(any(feature = "ktx2", feature = "dds"))]
+    InvalidData(String),
 }
 
 pub fn immutable_texture_from_bytes(
@@ -39,6 +57,19 @@ pub fn immutable_texture_from_bytes(
     byte_data: &[u8],
     dimensions: [u32; 2],
     format: vulkano::format::Format,
+) -> Result<Arc<ImageView>, ImageCreationError> {
+    upload_now(allocators, queue, |cbb| {
+        record_texture_from_bytes(allocators, cbb, byte_data, dimensions, format)
+    })
+}
+
+/// Records an upload into a new command buffer, then executes it and waits for it to finish
+pub(crate) fn upload_now(
+    allocators: &Allocators,
+    queue: Arc<Queue>,
+    record: impl FnOnce(
+        &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Result<Arc<ImageView>, ImageCreationError>,
 ) -> Result<Arc<ImageView>, ImageCreationError> {
     let mut cbb = AutoCommandBufferBuilder::primary(
         allocators.command_buffer.clone(),
@@ -46,7 +77,45 @@ pub fn immutable_texture_from_bytes(
         CommandBufferUsage::OneTimeSubmit,
     )
     .map_err(ImageCreationError::Vulkan)?;
+    let image = record(&mut cbb)?;
+    cbb.build()
+        .map_err(ImageCreationError::Vulkan)?
+        .execute(queue)
+        .unwrap()
+        .then_signal_fence_and_flush()
+        .map_err(ImageCreationError::Vulkan)?
+        .wait(None)
+        .map_err(ImageCreationError::Vulkan)?;
+    Ok(image)
+}
+
+/// Records the upload of tightly packed pixel bytes into `cbb`. The image may only be used once
+/// the command buffer has executed.
+pub(crate) fn record_texture_from_bytes(
+    allocators: &Allocators,
+    cbb: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    byte_data: &[u8],
+    dimensions: [u32; 2],
+    format: Format,
+) -> Result<Arc<ImageView>, ImageCreationError> {
+    texture_from_bytes_with_mapping(
+        allocators,
+        cbb,
+        byte_data,
+        dimensions,
+        format,
+        ComponentMapping::identity(),
+    )
+}
 
+fn texture_from_bytes_with_mapping(
+    allocators: &Allocators,
+    cbb: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    byte_data: &[u8],
+    dimensions: [u32; 2],
+    format: Format,
+    component_mapping: ComponentMapping,
+) -> Result<Arc<ImageView>, ImageCreationError> {
     let texture_data_buffer = Buffer::from_iter(
         allocators.memory.clone(),
         BufferCreateInfo { usage: BufferUsage::TRANSFER_SRC, ..Default::default() },
@@ -78,9 +147,11 @@ pub fn immutable_texture_from_bytes(
     ))
     .map_err(ImageCreationError::Validation)?;
 
-    let _fut = cbb.build().unwrap().execute(queue).unwrap();
-
-    Ok(ImageView::new_default(texture).unwrap())
+    Ok(ImageView::new(
+        texture.clone(),
+        ImageViewCreateInfo { component_mapping, ..ImageViewCreateInfo::from_image(&texture) },
+    )
+    .unwrap())
 }
 
 #[cfg(feature = "image")]
@@ -90,26 +161,121 @@ pub fn immutable_texture_from_file(
     file_bytes: &[u8],
     format: vulkano::format::Format,
 ) -> Result<Arc<ImageView>, ImageCreationError> {
-    use image::GenericImageView;
+    upload_now(allocators, queue, |cbb| {
+        record_texture_from_file(allocators, cbb, file_bytes, format)
+    })
+}
 
-    let img = image::load_from_memory(file_bytes).expect("Failed to load image from bytes");
-    let rgba = if let Some(rgba) = img.as_rgba8() {
-        rgba.to_owned().to_vec()
-    } else {
-        // Convert rgb to rgba
-        let rgb = img.as_rgb8().unwrap().to_owned();
-        let mut raw_data = vec![];
-        for val in rgb.chunks(3) {
-            raw_data.push(val[0]);
-            raw_data.push(val[1]);
-            raw_data.push(val[2]);
-            raw_data.push(255);
+/// Like `record_texture_from_bytes`, for encoded image files
+#[cfg(feature = "image")]
+pub(crate) fn record_texture_from_file(
+    allocators: &Allocators,
+    cbb: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    file_bytes: &[u8],
+    format: Format,
+) -> Result<Arc<ImageView>, ImageCreationError> {
+    let img = image::load_from_memory(file_bytes).map_err(ImageCreationError::Decode)?;
+    record_texture_from_dynamic(allocators, cbb, &img, format)
+}
+
+/// Uploads any `DynamicImage`. `format` is used for 8-bit RGB(A) images, e.g. to choose between
+/// sRGB and UNORM. Other images get a format matching their channels and bit depth:
+/// - Luma and luma + alpha: R8 / R8G8 (sRGB if `format` is), R16 / R16G16 UNORM, swizzled to gray
+/// - 16-bit RGB(A): R16G16B16A16_UNORM
+/// - 32-bit float RGB(A), e.g. HDR or EXR: R32G32B32A32_SFLOAT. Linear filtering of it isn't
+///   supported by all devices, use a nearest sampler if in doubt.
+///
+/// If the device can't sample the chosen format, the image is expanded to 8-bit RGBA in `format`.
+#[cfg(feature = "image")]
+pub fn immutable_texture_from_dynamic(
+    allocators: &Allocators,
+    queue: Arc<Queue>,
+    img: &DynamicImage,
+    format: vulkano::format::Format,
+) -> Result<Arc<ImageView>, ImageCreationError> {
+    upload_now(allocators, queue, |cbb| record_texture_from_dynamic(allocators, cbb, img, format))
+}
+
+/// Like `record_texture_from_bytes`, for decoded images
+#[cfg(feature = "image")]
+pub(crate) fn record_texture_from_dynamic(
+    allocators: &Allocators,
+    cbb: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    img: &DynamicImage,
+    format: Format,
+) -> Result<Arc<ImageView>, ImageCreationError> {
+    use vulkano::{
+        device::DeviceOwned,
+        format::{FormatFeatures, NumericFormat},
+        image::sampler::ComponentSwizzle,
+    };
+
+    let srgb = format.numeric_format_color() == Some(NumericFormat::SRGB);
+    // Gray from the red channel, alpha from green (if any)
+    let gray = |alpha| ComponentMapping {
+        r: ComponentSwizzle::Red,
+        g: ComponentSwizzle::Red,
+        b: ComponentSwizzle::Red,
+        a: alpha,
+    };
+    let dimensions = [img.width(), img.height()];
+    // Also the fallback for formats the device can't sample
+    let rgba_format = format;
+    let (bytes, format, component_mapping) = match img {
+        DynamicImage::ImageRgba8(rgba) => {
+            (rgba.as_raw().clone(), format, ComponentMapping::identity())
         }
-        let new_rgba = RgbaImage::from_raw(rgb.width(), rgb.height(), raw_data).unwrap();
-        new_rgba.to_vec()
+        DynamicImage::ImageLuma8(luma) => (
+            luma.as_raw().clone(),
+            if srgb { Format::R8_SRGB } else { Format::R8_UNORM },
+            gray(ComponentSwizzle::One),
+        ),
+        DynamicImage::ImageLumaA8(luma_alpha) => (
+            luma_alpha.as_raw().clone(),
+            if srgb { Format::R8G8_SRGB } else { Format::R8G8_UNORM },
+            gray(ComponentSwizzle::Green),
+        ),
+        DynamicImage::ImageLuma16(luma) => (
+            ne_bytes(luma.as_raw(), u16::to_ne_bytes),
+            Format::R16_UNORM,
+            gray(ComponentSwizzle::One),
+        ),
+        DynamicImage::ImageLumaA16(luma_alpha) => (
+            ne_bytes(luma_alpha.as_raw(), u16::to_ne_bytes),
+            Format::R16G16_UNORM,
+            gray(ComponentSwizzle::Green),
+        ),
+        // Three channel formats are rarely supported for sampling, these get an alpha channel
+        DynamicImage::ImageRgb16(_) | DynamicImage::ImageRgba16(_) => (
+            ne_bytes(img.to_rgba16().as_raw(), u16::to_ne_bytes),
+            Format::R16G16B16A16_UNORM,
+            ComponentMapping::identity(),
+        ),
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => (
+            ne_bytes(img.to_rgba32f().as_raw(), f32::to_ne_bytes),
+            Format::R32G32B32A32_SFLOAT,
+            ComponentMapping::identity(),
+        ),
+        // RGB8 and any future variants
+        _ => (img.to_rgba8().into_raw(), format, ComponentMapping::identity()),
     };
-    let dimensions = img.dimensions();
-    immutable_texture_from_bytes(allocators, queue, &rgba, [dimensions.0, dimensions.1], format)
+    let sampled = |format| {
+        cbb.device().physical_device().format_properties(format).is_ok_and(|properties| {
+            properties.optimal_tiling_features.contains(FormatFeatures::SAMPLED_IMAGE)
+        })
+    };
+    let (bytes, format, component_mapping) = if format == rgba_format || sampled(format) {
+        (bytes, format, component_mapping)
+    } else {
+        (img.to_rgba8().into_raw(), rgba_format, ComponentMapping::identity())
+    };
+    texture_from_bytes_with_mapping(allocators, cbb, &bytes, dimensions, format, component_mapping)
+}
+
+/// Bytes of the samples in native endianness, as Vulkan expects them
+#[cfg(feature = "image")]
+fn ne_bytes<T: Copy, const N: usize>(samples: &[T], to_bytes: fn(T) -> [u8; N]) -> Vec<u8> {
+    samples.iter().flat_map(|&sample| to_bytes(sample)).collect()
 }
 
 pub struct Allocators {
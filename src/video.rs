@@ -0,0 +1,288 @@
+// Copyright (c) 2021 Okko Hakola
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use std::{fmt, sync::Arc};
+
+use vulkano::{
+    buffer::Subbuffer,
+    descriptor_set::DescriptorSet,
+    device::Device,
+    format::{Format, FormatFeatures},
+    image::{
+        sampler::ycbcr::{
+            ChromaLocation, SamplerYcbcrConversion, SamplerYcbcrConversionCreateInfo,
+            SamplerYcbcrModelConversion, SamplerYcbcrRange,
+        },
+        sampler::Filter,
+        view::ImageView,
+        Image, ImageAspects,
+    },
+    pipeline::GraphicsPipeline,
+    Validated, VulkanError,
+};
+
+/// Layout of the planes of a YUV 4:2:0 video frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum YuvFormat {
+    /// Y plane followed by a plane of interleaved U and V, `G8_B8R8_2PLANE_420_UNORM`
+    Nv12,
+    /// Y, U and V planes, `G8_B8_R8_3PLANE_420_UNORM`
+    I420,
+}
+
+impl YuvFormat {
+    /// The multi-planar format sampled through a YCbCr conversion
+    pub fn multi_planar_format(self) -> Format {
+        match self {
+            YuvFormat::Nv12 => Format::G8_B8R8_2PLANE_420_UNORM,
+            YuvFormat::I420 => Format::G8_B8_R8_3PLANE_420_UNORM,
+        }
+    }
+
+    pub fn plane_count(self) -> usize {
+        match self {
+            YuvFormat::Nv12 => 2,
+            YuvFormat::I420 => 3,
+        }
+    }
+
+    /// Format of a plane when the planes are separate images
+    pub(crate) fn plane_format(self, plane: usize) -> Format {
+        match (self, plane) {
+            (YuvFormat::Nv12, 1) => Format::R8G8_UNORM,
+            _ => Format::R8_UNORM,
+        }
+    }
+
+    /// Size of a plane in texels, chroma planes having half the resolution
+    pub(crate) fn plane_extent(self, extent: [u32; 2], plane: usize) -> [u32; 2] {
+        match plane {
+            0 => extent,
+            _ => extent.map(|side| side.div_ceil(2)),
+        }
+    }
+
+    /// Bytes per texel of a plane
+    fn texel_size(self, plane: usize) -> usize {
+        match (self, plane) {
+            (YuvFormat::Nv12, 1) => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// Color matrix a video was encoded with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum YuvColorSpace {
+    /// SD video
+    Bt601,
+    /// HD video
+    #[default]
+    Bt709,
+    /// UHD video
+    Bt2020,
+}
+
+impl YuvColorSpace {
+    /// Factors of Cr to R, Cb to G, Cr to G and Cb to B
+    pub(crate) fn coefficients(self) -> [f32; 4] {
+        let (kr, kb) = match self {
+            YuvColorSpace::Bt601 => (0.299, 0.114),
+            YuvColorSpace::Bt709 => (0.2126, 0.0722),
+            YuvColorSpace::Bt2020 => (0.2627, 0.0593),
+        };
+        let kg = 1.0 - kr - kb;
+        [2.0 * (1.0 - kr), 2.0 * kb * (1.0 - kb) / kg, 2.0 * kr * (1.0 - kr) / kg, 2.0 * (1.0 - kb)]
+    }
+}
+
+/// Range of the encoded values
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum YuvRange {
+    /// 16-235 for Y, 16-240 for U and V, as used by most video
+    #[default]
+    Limited,
+    /// 0-255
+    Full,
+}
+
+/// One plane of a video frame in CPU memory
+#[derive(Clone, Copy, Debug)]
+pub struct VideoPlane<'a> {
+    pub data: &'a [u8],
+    /// Bytes from the start of one row to the next, at least the plane's width in bytes
+    pub stride: usize,
+}
+
+/// Why a video texture couldn't be created or updated
+#[derive(Clone, Debug)]
+pub enum VideoError {
+    /// No video texture is registered under this id
+    UnknownTexture(egui::TextureId),
+    /// 4:2:0 frames must have an even, nonzero width and height
+    InvalidExtent([u32; 2]),
+    /// The frame doesn't have as many planes as its format
+    PlaneCount { expected: usize, actual: usize },
+    /// A plane's stride is shorter than a row or not a multiple of its texel size
+    InvalidStride { plane: usize },
+    /// A plane has less data than its rows span
+    PlaneTooSmall { plane: usize, expected: usize, actual: usize },
+    /// The images the frame would be uploaded into are still used by a pending frame, more
+    /// frames are in flight than `GuiConfig::frames_in_flight`
+    SlotInUse,
+    /// Submitting the upload failed
+    Upload(Validated<VulkanError>),
+}
+
+impl fmt::Display for VideoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VideoError::UnknownTexture(id) => {
+                write!(f, "no video texture is registered as {:?}", id)
+            }
+            VideoError::InvalidExtent(extent) => {
+                write!(f, "frame size {:?} isn't even and nonzero", extent)
+            }
+            VideoError::PlaneCount { expected, actual } => {
+                write!(f, "expected {} planes, got {}", expected, actual)
+            }
+            VideoError::InvalidStride { plane } => write!(f, "invalid stride of plane {}", plane),
+            VideoError::PlaneTooSmall { plane, expected, actual } => {
+                write!(f, "plane {} has {} bytes, expected at least {}", plane, actual, expected)
+            }
+            VideoError::SlotInUse => write!(f, "video images are still used by a pending frame"),
+            VideoError::Upload(err) => write!(f, "failed to upload the frame: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for VideoError {}
+
+/// Images a frame is uploaded into, and the staging buffer it is copied from. Videos have
+/// `frames_in_flight + 1` of them, so that a frame is never written into one a pending frame may
+/// still use.
+pub(crate) struct VideoSlot {
+    // One multi-planar image with a YCbCr conversion, or one image per plane
+    pub images: Vec<Arc<Image>>,
+    pub view: Arc<ImageView>,
+    pub desc_set: Arc<DescriptorSet>,
+    // Reused whenever the slot comes around again, grown when a frame needs more
+    pub staging: Option<Subbuffer<[u8]>>,
+}
+
+pub(crate) struct VideoTexture {
+    pub format: YuvFormat,
+    pub extent: [u32; 2],
+    pub color_space: YuvColorSpace,
+    pub range: YuvRange,
+    // Own pipeline with the conversion's immutable sampler, None when planes are converted by
+    // the fallback shader
+    pub ycbcr_pipeline: Option<Arc<GraphicsPipeline>>,
+    pub slots: Vec<VideoSlot>,
+    // Slot of the last uploaded frame, None before the first one
+    pub current_slot: Option<usize>,
+}
+
+impl VideoTexture {
+    /// Where each plane goes in the staging buffer, after checking the planes against the format
+    pub fn plane_offsets(&self, planes: &[VideoPlane<'_>]) -> Result<Vec<usize>, VideoError> {
+        let expected = self.format.plane_count();
+        if planes.len() != expected {
+            return Err(VideoError::PlaneCount { expected, actual: planes.len() });
+        }
+        let mut offsets = vec![];
+        let mut offset = 0;
+        for (index, plane) in planes.iter().enumerate() {
+            let [width, height] = self.format.plane_extent(self.extent, index);
+            let texel_size = self.format.texel_size(index);
+            let row_size = width as usize * texel_size;
+            if plane.stride < row_size || plane.stride % texel_size != 0 {
+                return Err(VideoError::InvalidStride { plane: index });
+            }
+            let expected = plane.stride * (height as usize - 1) + row_size;
+            if plane.data.len() < expected {
+                return Err(VideoError::PlaneTooSmall {
+                    plane: index,
+                    expected,
+                    actual: plane.data.len(),
+                });
+            }
+            offsets.push(offset);
+            // Copies into images need texel aligned buffer offsets
+            offset += expected.next_multiple_of(4);
+        }
+        offsets.push(offset);
+        Ok(offsets)
+    }
+
+    /// Aspect of the image a plane is copied into
+    pub fn plane_aspect(&self, plane: usize) -> ImageAspects {
+        match (self.ycbcr_pipeline.is_some(), plane) {
+            (false, _) => ImageAspects::COLOR,
+            (true, 0) => ImageAspects::PLANE_0,
+            (true, 1) => ImageAspects::PLANE_1,
+            (true, _) => ImageAspects::PLANE_2,
+        }
+    }
+
+    /// Bytes per texel of a plane, to turn strides into row lengths
+    pub fn texel_size(&self, plane: usize) -> usize {
+        self.format.texel_size(plane)
+    }
+}
+
+/// The YCbCr conversion for sampling `format` directly, if the device supports one
+pub(crate) fn ycbcr_conversion(
+    device: &Arc<Device>,
+    format: YuvFormat,
+    color_space: YuvColorSpace,
+    range: YuvRange,
+) -> Option<Arc<SamplerYcbcrConversion>> {
+    if !device.enabled_features().sampler_ycbcr_conversion {
+        return None;
+    }
+    let format = format.multi_planar_format();
+    let features = device.physical_device().format_properties(format).ok()?.optimal_tiling_features;
+    if !features.contains(FormatFeatures::SAMPLED_IMAGE | FormatFeatures::TRANSFER_DST) {
+        return None;
+    }
+    // MPEG-2 and later codecs site chroma samples with the even luma columns
+    let chroma_location = if features.intersects(FormatFeatures::COSITED_CHROMA_SAMPLES) {
+        ChromaLocation::CositedEven
+    } else if features.intersects(FormatFeatures::MIDPOINT_CHROMA_SAMPLES) {
+        ChromaLocation::Midpoint
+    } else {
+        return None;
+    };
+    let chroma_filter =
+        if features.intersects(FormatFeatures::SAMPLED_IMAGE_YCBCR_CONVERSION_LINEAR_FILTER) {
+            Filter::Linear
+        } else {
+            Filter::Nearest
+        };
+    SamplerYcbcrConversion::new(
+        device.clone(),
+        SamplerYcbcrConversionCreateInfo {
+            format,
+            ycbcr_model: match color_space {
+                YuvColorSpace::Bt601 => SamplerYcbcrModelConversion::Ycbcr601,
+                YuvColorSpace::Bt709 => SamplerYcbcrModelConversion::Ycbcr709,
+                YuvColorSpace::Bt2020 => SamplerYcbcrModelConversion::Ycbcr2020,
+            },
+            ycbcr_range: match range {
+                YuvRange::Limited => SamplerYcbcrRange::ItuNarrow,
+                YuvRange::Full => SamplerYcbcrRange::ItuFull,
+            },
+            chroma_offset: [chroma_location; 2],
+            chroma_filter,
+            ..Default::default()
+        },
+    )
+    .ok()
+}